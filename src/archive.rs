@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use clap::ValueEnum;
+
+use crate::{download::DownloadJob, manifest::relative_path};
+
+/// Name of the archive holding a whole run, next to the per subject ones.
+const RUN_ARCHIVE: &str = "papers";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    Zip,
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// What one archive holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveScope {
+    /// One archive per subject, e.g. "Mathematics (9709).zip".
+    Subject,
+    /// Every paper of the run in "papers.zip".
+    Run,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveOptions {
+    pub format: ArchiveFormat,
+    pub scope: ArchiveScope,
+    /// Add to the existing archives instead of replacing them, papers they hold already are skipped.
+    pub append: bool,
+}
+
+enum Writer {
+    Zip(Box<zip::ZipWriter<File>>),
+    TarZst(tar::Builder<zstd::Encoder<'static, File>>),
+}
+
+/// An archive being written next to its final path, renamed into place once complete.
+struct Archive {
+    path: PathBuf,
+    temporary: PathBuf,
+    writer: Writer,
+    /// Entry names, separated by `/`.
+    entries: HashSet<String>,
+    /// Papers added this run.
+    added: usize,
+    /// Papers of this archive that failed to download this run.
+    failed: usize,
+    /// Whether finishing replaces an existing archive without its entries having been copied.
+    replaces: bool,
+}
+
+fn zip_error(e: zip::result::ZipError) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+impl Archive {
+    /// Starts a new archive for `path`, holding the entries of the existing one when appending.
+    fn create(path: PathBuf, format: ArchiveFormat, append: bool) -> Result<Archive, std::io::Error> {
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".partial");
+        let temporary = PathBuf::from(temporary);
+        let file = File::create(&temporary)?;
+        let replaces = !append && path.exists();
+        match Archive::start(file, format, append.then_some(path.as_path()).filter(|x| x.exists())) {
+            Ok((writer, entries)) => Ok(Archive {
+                path,
+                temporary,
+                writer,
+                entries,
+                added: 0,
+                failed: 0,
+                replaces,
+            }),
+            Err(e) => {
+                let _ = std::fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens the writer, copying the entries of `existing` into it.
+    fn start(file: File, format: ArchiveFormat, existing: Option<&Path>) -> Result<(Writer, HashSet<String>), std::io::Error> {
+        let mut entries = HashSet::new();
+        let existing = existing.map(File::open).transpose()?;
+        let writer = match format {
+            ArchiveFormat::Zip => {
+                let mut writer = zip::ZipWriter::new(file);
+                if let Some(existing) = existing {
+                    let mut existing = zip::ZipArchive::new(existing).map_err(zip_error)?;
+                    for i in 0..existing.len() {
+                        let entry = existing.by_index_raw(i).map_err(zip_error)?;
+                        entries.insert(entry.name().to_string());
+                        writer.raw_copy_file(entry).map_err(zip_error)?;
+                    }
+                }
+                Writer::Zip(Box::new(writer))
+            }
+            ArchiveFormat::TarZst => {
+                let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+                // A tar stream ends with zero blocks, appending means rewriting the entries into a new one.
+                if let Some(existing) = existing {
+                    let mut existing = tar::Archive::new(zstd::Decoder::new(existing)?);
+                    for entry in existing.entries()? {
+                        let mut entry = entry?;
+                        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+                        let mut header = entry.header().clone();
+                        builder.append_data(&mut header, &name, &mut entry)?;
+                        entries.insert(name);
+                    }
+                }
+                Writer::TarZst(builder)
+            }
+        };
+        Ok((writer, entries))
+    }
+
+    fn add(&mut self, name: &str, bytes: &[u8]) -> Result<(), std::io::Error> {
+        match &mut self.writer {
+            Writer::Zip(writer) => {
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated);
+                writer.start_file(name, options).map_err(zip_error)?;
+                std::io::Write::write_all(writer, bytes)?;
+            }
+            Writer::TarZst(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|x| x.as_secs())
+                        .unwrap_or_default(),
+                );
+                builder.append_data(&mut header, name, bytes)?;
+            }
+        }
+        self.entries.insert(name.to_string());
+        self.added += 1;
+        Ok(())
+    }
+
+    /// Completes the archive and moves it over the previous one.
+    fn finish(self) -> Result<PathBuf, std::io::Error> {
+        let finished = match self.writer {
+            Writer::Zip(writer) => (*writer).finish().map(|_| ()).map_err(zip_error),
+            Writer::TarZst(builder) => builder.into_inner().and_then(|x| x.finish()).map(|_| ()),
+        };
+        match finished.and_then(|_| std::fs::rename(&self.temporary, &self.path)) {
+            Ok(_) => Ok(self.path),
+            Err(e) => {
+                let _ = std::fs::remove_file(&self.temporary);
+                Err(e)
+            }
+        }
+    }
+
+    /// Leaves the previous archive untouched.
+    fn abandon(self) {
+        drop(self.writer);
+        let _ = std::fs::remove_file(&self.temporary);
+    }
+}
+
+/// The entry names of an existing archive.
+pub fn entries(path: &Path, format: ArchiveFormat) -> Result<HashSet<String>, std::io::Error> {
+    let file = File::open(path)?;
+    match format {
+        ArchiveFormat::Zip => {
+            let archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+            Ok(archive.file_names().map(str::to_string).collect())
+        }
+        ArchiveFormat::TarZst => {
+            let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+            let mut entries = HashSet::new();
+            for entry in archive.entries()? {
+                entries.insert(entry?.path()?.to_string_lossy().replace('\\', "/"));
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// The archive a paper goes into and its name inside, the path the layout gives it below `root`.
+pub fn destination(root: &Path, options: ArchiveOptions, job: &DownloadJob) -> (PathBuf, String) {
+    let archive = match options.scope {
+        ArchiveScope::Subject => format!("{} ({})", job.syllabus_code.name, job.syllabus_code.syllabus_code)
+            .replace(['/', '\\'], "-"),
+        ArchiveScope::Run => RUN_ARCHIVE.to_string(),
+    };
+    let name = relative_path(root, &job.path).unwrap_or_else(|| job.paper.get_ref_filename(&job.syllabus_code));
+    (root.join(format!("{}.{}", archive, options.format.extension())), name)
+}
+
+/// The archives of a download, papers go in under the path the layout gives them in a folder.
+pub struct Archives {
+    root: PathBuf,
+    options: ArchiveOptions,
+    archives: HashMap<PathBuf, Mutex<Archive>>,
+}
+
+impl Archives {
+    /// Opens the archives `jobs` go into, in `root`.
+    pub fn open(root: &Path, options: ArchiveOptions, jobs: &[DownloadJob]) -> Result<Archives, std::io::Error> {
+        let mut archives = Archives {
+            root: root.to_path_buf(),
+            options,
+            archives: HashMap::new(),
+        };
+        for job in jobs {
+            let (path, _) = destination(root, options, job);
+            if archives.archives.contains_key(&path) {
+                continue;
+            }
+            let archive = match Archive::create(path.clone(), options.format, options.append) {
+                Ok(archive) => archive,
+                Err(e) => {
+                    archives.abandon();
+                    return Err(std::io::Error::new(e.kind(), format!("{:?}: {}", path, e)));
+                }
+            };
+            archives.archives.insert(path, Mutex::new(archive));
+        }
+        Ok(archives)
+    }
+
+    /// Whether the archive of `job` holds the paper already.
+    pub fn contains(&self, job: &DownloadJob) -> bool {
+        let (path, name) = destination(&self.root, self.options, job);
+        match self.archives.get(&path) {
+            Some(archive) => archive.lock().unwrap().entries.contains(&name),
+            None => false,
+        }
+    }
+
+    pub fn add(&self, job: &DownloadJob, bytes: &[u8]) -> Result<(), std::io::Error> {
+        let (path, name) = destination(&self.root, self.options, job);
+        let archive = self
+            .archives
+            .get(&path)
+            .ok_or(std::io::Error::other("no archive was opened for this paper"))?;
+        archive.lock().unwrap().add(&name, bytes)
+    }
+
+    /// Records that the paper of `job` could not be downloaded into its archive.
+    pub fn failed(&self, job: &DownloadJob) {
+        let (path, _) = destination(&self.root, self.options, job);
+        if let Some(archive) = self.archives.get(&path) {
+            archive.lock().unwrap().failed += 1;
+        }
+    }
+
+    /// Completes every archive that gained papers, returning the ones written and how many failed or were kept.
+    pub fn finish(self) -> (Vec<PathBuf>, usize) {
+        let (mut written, mut failed) = (vec![], 0);
+        for archive in self.archives.into_values() {
+            let archive = archive.into_inner().unwrap();
+            // Rewriting an archive to add nothing would only risk it, or replace it with an empty one.
+            if archive.added == 0 {
+                archive.abandon();
+                continue;
+            }
+            // The rebuilt archive lacks the papers that failed, the previous one may still hold them.
+            if archive.replaces && archive.failed > 0 {
+                warn!(
+                    "{} papers of {:?} failed to download, keeping the previous archive. Run again or pass --append.",
+                    archive.failed, archive.path
+                );
+                archive.abandon();
+                failed += 1;
+                continue;
+            }
+            let path = archive.path.clone();
+            match archive.finish() {
+                Ok(path) => written.push(path),
+                Err(e) => {
+                    error!("Failed to write archive {:?}: {}", path, e);
+                    failed += 1;
+                }
+            }
+        }
+        written.sort();
+        (written, failed)
+    }
+
+    /// Drops the archives being written, leaving the existing ones as they were.
+    pub fn abandon(self) {
+        for archive in self.archives.into_values() {
+            archive.into_inner().unwrap().abandon();
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::{config_format::ConfigFormat, config_migrate::migrate, configuration::Configuration};
+
+/// Lists appended when a file is included, every other field of the including file replaces the included one.
+const APPENDED: [&str; 2] = ["subjects", "rules"];
+
+#[derive(Debug)]
+pub struct ShowConfiguration {
+    pub config: PathBuf,
+    pub format: Option<ConfigFormat>,
+    pub profile: Option<String>,
+    pub resolved: bool,
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Layers `overlay` on top of `base`. Tables merge key by key, `append` lists are concatenated.
+fn merge(base: &mut Table, overlay: Table, append: &[&str]) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay, append),
+            (Some(Value::Array(base)), Value::Array(overlay)) if append.contains(&key.as_str()) => {
+                base.extend(overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The files a raw configuration includes, relative to the directory of `path`.
+pub fn includes(config: &Table, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    match config.get("include") {
+        None => Ok(vec![]),
+        Some(Value::String(x)) => Ok(vec![directory.join(x)]),
+        Some(Value::Array(x)) => x
+            .iter()
+            .map(|y| match y {
+                Value::String(y) => Ok(directory.join(y)),
+                y => Err(invalid(format!("Invalid include {} in {:?}", y, path))),
+            })
+            .collect(),
+        Some(x) => Err(invalid(format!("Invalid include {} in {:?}", x, path))),
+    }
+}
+
+fn load_layer(path: &Path, format: ConfigFormat, chain: &mut Vec<PathBuf>) -> Result<Table, std::io::Error> {
+    let canonical = path.canonicalize().map_err(|e| {
+        std::io::Error::new(e.kind(), format!("Failed to open {:?}: {}", path, e))
+    })?;
+    if chain.contains(&canonical) {
+        let cycle = chain
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|x| x.display().to_string())
+            .collect::<Vec<_>>();
+        return Err(invalid(format!("Include cycle: {}", cycle.join(" -> "))));
+    }
+    let content = std::fs::read_to_string(path)?;
+    let mut config = migrate(
+        format
+            .parse(&content)
+            .map_err(|e| invalid(format!("Failed to parse {:?}: {}", path, e)))?,
+    )?;
+
+    chain.push(canonical);
+    let mut layered = Table::new();
+    for include in includes(&config, path)? {
+        let included = load_layer(&include, ConfigFormat::from_path(&include), chain)?;
+        merge(&mut layered, included, &APPENDED);
+    }
+    chain.pop();
+
+    config.remove("include");
+    merge(&mut layered, config, &APPENDED);
+    Ok(layered)
+}
+
+/// Loads a configuration with every include merged in, still holding its profiles.
+/// Included files are read in order, the including file is layered on top.
+pub fn load_layers(path: &Path, format: Option<ConfigFormat>) -> Result<Table, std::io::Error> {
+    load_layer(path, ConfigFormat::resolve(format, path), &mut vec![])
+}
+
+/// Names of the profiles a composed configuration defines.
+pub fn profile_names(config: &Table) -> Vec<String> {
+    config
+        .get("profile")
+        .and_then(Value::as_table)
+        .map(|x| x.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Drops the profiles of a composed configuration, applying `profile` on top of the base first.
+/// A profile replaces the fields it sets, so its subjects are the only ones selected.
+pub fn apply_profile(mut config: Table, profile: Option<&str>) -> Result<Table, std::io::Error> {
+    let names = profile_names(&config);
+    let mut profiles = match config.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(x) => return Err(invalid(format!("Invalid profile table {}", x))),
+        None => Table::new(),
+    };
+    let profile = match profile {
+        Some(profile) => profile,
+        None => return Ok(config),
+    };
+    match profiles.remove(profile) {
+        Some(Value::Table(overlay)) => {
+            merge(&mut config, overlay, &[]);
+            Ok(config)
+        }
+        Some(x) => Err(invalid(format!("Invalid profile \"{}\": {}", profile, x))),
+        None if names.is_empty() => Err(invalid(format!(
+            "Unknown profile \"{}\", the configuration defines none",
+            profile
+        ))),
+        None => Err(invalid(format!(
+            "Unknown profile \"{}\", available profiles: {}",
+            profile,
+            names.join(", ")
+        ))),
+    }
+}
+
+pub fn handle_show(config: ShowConfiguration) {
+    let format = ConfigFormat::resolve(config.format, &config.config);
+    let shown = match config.resolved {
+        true => Configuration::load_profile(&config.config, config.format, config.profile.as_deref())
+            .and_then(|x| format.serialize(&x).map_err(invalid)),
+        false => std::fs::read_to_string(&config.config),
+    };
+    match shown {
+        Ok(shown) => print!("{}", shown),
+        Err(e) => {
+            error!("Error reading configuration file: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
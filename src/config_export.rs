@@ -0,0 +1,176 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    config_format::ConfigFormat,
+    config_gen::runtime,
+    configuration::{Configuration, PaperType, Season},
+    download::select_papers,
+    layout::Layout,
+    scraper::paper_url,
+};
+
+/// Listings other download tools understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UrlListFormat {
+    /// One URL per line, e.g. for `wget -i`.
+    Urls,
+    /// `aria2c --input-file`, each URL with its target directory and file name.
+    Aria2,
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct ExportConfiguration {
+    pub config: PathBuf,
+    pub config_format: Option<ConfigFormat>,
+    pub profile: Option<String>,
+    /// Folder the target paths are built under, as `download --output` would.
+    pub output_folder: PathBuf,
+    /// Overrides the layout of the configuration and the library.
+    pub layout: Option<Layout>,
+    pub format: UrlListFormat,
+    /// Written to stdout when missing.
+    pub output: Option<PathBuf>,
+    pub threads: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedPaper {
+    pub syllabus_code: String,
+    pub subject: String,
+    pub year: String,
+    pub season: Season,
+    pub paper_type: PaperType,
+    pub variant: String,
+    pub url: String,
+    pub path: PathBuf,
+}
+
+/// Every paper `handle_download` would fetch for `config`, with its URL and target path.
+pub fn export_papers(config: &Configuration, layout: &Layout, output_folder: &std::path::Path, threads: u8) -> Vec<ExportedPaper> {
+    let selected = select_papers(config, &runtime(threads), threads).unwrap_or_else(|e| {
+        warn!("{} listings could not be fetched, their papers are missing.", e.failed);
+        *e.resolved
+    });
+    let papers = selected
+        .subjects
+        .iter()
+        .flat_map(|subject| {
+            subject.papers.iter().map(|paper| ExportedPaper {
+                syllabus_code: subject.syllabus_code.syllabus_code.clone(),
+                subject: subject.syllabus_code.name.clone(),
+                year: paper.year.clone(),
+                season: paper.season.clone(),
+                paper_type: paper.paper_type.clone(),
+                variant: paper.variant.clone(),
+                url: paper_url(&subject.syllabus_code, paper),
+                path: layout.path(output_folder, &subject.syllabus_code, paper),
+            })
+        })
+        .collect::<Vec<_>>();
+    unique_urls(papers)
+}
+
+/// A paper listed twice, or selected by both a rule and an explicit entry, is fetched once, as `download` does.
+fn unique_urls(papers: Vec<ExportedPaper>) -> Vec<ExportedPaper> {
+    let mut seen = HashSet::new();
+    papers.into_iter().filter(|x| seen.insert(x.url.clone())).collect()
+}
+
+fn render(papers: &[ExportedPaper], format: UrlListFormat) -> Result<String, String> {
+    match format {
+        UrlListFormat::Urls => Ok(papers.iter().map(|x| format!("{}\n", x.url)).collect()),
+        UrlListFormat::Aria2 => Ok(papers
+            .iter()
+            .map(|x| {
+                let dir = x.path.parent().map(|y| y.display().to_string()).unwrap_or_default();
+                let out = x.path.file_name().map(|y| y.to_string_lossy().to_string()).unwrap_or_default();
+                format!("{}\n  dir={}\n  out={}\n", x.url, dir, out)
+            })
+            .collect()),
+        UrlListFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for paper in papers {
+                writer.serialize(paper).map_err(|e| e.to_string())?;
+            }
+            let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+            String::from_utf8(bytes).map_err(|e| e.to_string())
+        }
+        UrlListFormat::Json => serde_json::to_string_pretty(papers).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn handle_export(config: ExportConfiguration) {
+    let configuration = match Configuration::load_profile(&config.config, config.config_format, config.profile.as_deref()) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            error!("Error parsing configuration file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let layout = match Layout::for_library(config.layout.as_ref().or(configuration.layout.as_ref()), &config.output_folder) {
+        Ok(layout) => layout,
+        Err(e) => {
+            error!("Invalid library layout: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let papers = export_papers(&configuration, &layout, &config.output_folder, config.threads);
+    let mut shared = papers
+        .iter()
+        .filter(|x| papers.iter().any(|y| y.path == x.path && y.url != x.url))
+        .map(|x| &x.path)
+        .collect::<Vec<_>>();
+    shared.sort();
+    shared.dedup();
+    if !shared.is_empty() {
+        warn!(
+            "Layout \"{}\" saves different papers to {} shared paths, e.g. {:?}.",
+            layout,
+            shared.len(),
+            shared[0]
+        );
+    }
+    let rendered = match render(&papers, config.format) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            error!("Failed to export papers: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match &config.output {
+        Some(output) => match std::fs::write(output, rendered) {
+            Ok(_) => info!("Exported {} papers to {:?}", papers.len(), output),
+            Err(e) => {
+                error!("Failed to write {:?}: {}", output, e);
+                std::process::exit(1);
+            }
+        },
+        None => print!("{}", rendered),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn papers_are_listed_once() {
+        let paper = |variant: &str| ExportedPaper {
+            syllabus_code: "9709".to_string(),
+            subject: "Mathematics".to_string(),
+            year: "2023".to_string(),
+            season: Season::Summer,
+            paper_type: PaperType::QP,
+            variant: variant.to_string(),
+            url: format!("https://example.com/9709_s23_qp_{}.pdf", variant),
+            path: PathBuf::from(format!("9709_s23_qp_{}.pdf", variant)),
+        };
+        let papers = unique_urls(vec![paper("12"), paper("13"), paper("12")]);
+        assert_eq!(render(&papers, UrlListFormat::Urls).unwrap().lines().count(), 2);
+    }
+}
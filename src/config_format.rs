@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use yaml_rust2::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+/// File formats a `Configuration` can be read from and written to. All of them map onto the same serde model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Picks the format from the file extension, falling back to TOML.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_lowercase())
+            .as_deref()
+        {
+            Some("json") => ConfigFormat::Json,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Toml,
+        }
+    }
+
+    /// An explicit format wins over the file extension.
+    pub fn resolve(explicit: Option<ConfigFormat>, path: &Path) -> ConfigFormat {
+        explicit.unwrap_or(ConfigFormat::from_path(path))
+    }
+
+    /// Parses a configuration into a raw table, ready for migration.
+    pub fn parse(&self, content: &str) -> Result<toml::Table, String> {
+        match self {
+            ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => {
+                let documents = YamlLoader::load_from_str(content).map_err(|e| e.to_string())?;
+                match documents.into_iter().next() {
+                    Some(document) => serde_json::from_value(from_yaml(document)?).map_err(|e| e.to_string()),
+                    None => Ok(toml::Table::new()),
+                }
+            }
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, config: &T) -> Result<String, String> {
+        match self {
+            ConfigFormat::Toml => toml::to_string(config).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => {
+                let value = serde_json::to_value(config).map_err(|e| e.to_string())?;
+                let mut output = String::new();
+                YamlEmitter::new(&mut output)
+                    .dump(&to_yaml(value))
+                    .map_err(|e| e.to_string())?;
+                let output = output.strip_prefix("---\n").unwrap_or(&output);
+                Ok(format!("{}\n", output))
+            }
+        }
+    }
+}
+
+/// Turns a YAML document into the serde model the other formats share. Null mapping values count as unset.
+fn from_yaml(yaml: Yaml) -> Result<serde_json::Value, String> {
+    use serde_json::Value;
+    Ok(match yaml {
+        Yaml::String(x) => Value::String(x),
+        Yaml::Integer(x) => Value::from(x),
+        Yaml::Real(x) => match x.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+            Some(x) => Value::Number(x),
+            None => return Err(format!("Invalid number {}", x)),
+        },
+        Yaml::Boolean(x) => Value::Bool(x),
+        Yaml::Array(items) => Value::Array(items.into_iter().map(from_yaml).collect::<Result<_, _>>()?),
+        Yaml::Hash(entries) => {
+            let mut map = serde_json::Map::new();
+            for (key, value) in entries {
+                let key = match key {
+                    Yaml::String(x) => x,
+                    Yaml::Integer(x) => x.to_string(),
+                    Yaml::Boolean(x) => x.to_string(),
+                    key => return Err(format!("Unsupported mapping key {:?}", key)),
+                };
+                if value != Yaml::Null {
+                    map.insert(key, from_yaml(value)?);
+                }
+            }
+            Value::Object(map)
+        }
+        Yaml::Null => return Err("Null values are only allowed for mapping entries".to_string()),
+        yaml => return Err(format!("Unsupported YAML value {:?}", yaml)),
+    })
+}
+
+fn to_yaml(value: serde_json::Value) -> Yaml {
+    use serde_json::Value;
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(x) => Yaml::Boolean(x),
+        Value::Number(x) => match x.as_i64() {
+            Some(x) => Yaml::Integer(x),
+            None => Yaml::Real(x.to_string()),
+        },
+        Value::String(x) => Yaml::String(x),
+        Value::Array(items) => Yaml::Array(items.into_iter().map(to_yaml).collect()),
+        Value::Object(entries) => {
+            let mut hash = Hash::new();
+            for (key, value) in entries {
+                hash.insert(Yaml::String(key), to_yaml(value));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+/// Writes next to the target and renames, so a failure never leaves a half written configuration.
+pub fn write_atomically(path: &Path, content: &str) -> Result<(), std::io::Error> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, content).and_then(|_| std::fs::rename(&temporary, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+version: 2
+papers: [QP, MS]
+layout: "{code}/{session}/{filename}"
+subjects:
+  - syllabus_code:
+      name: Mathematics
+      syllabus_code: "9709"
+      access_slug: mathematics-(9709)
+    paper_types: ~
+    papers:
+      - { year: "2023", season: Summer, paper_type: QP, variant: "12" }
+"#;
+
+    #[test]
+    fn yaml_round_trips() {
+        let table = ConfigFormat::Yaml.parse(YAML).unwrap();
+        assert_eq!(table["version"].as_integer(), Some(2));
+        // Null entries are left out, like unset options.
+        assert!(table["subjects"][0].get("paper_types").is_none());
+        let written = ConfigFormat::Yaml.serialize(&table).unwrap();
+        assert_eq!(ConfigFormat::Yaml.parse(&written).unwrap(), table);
+    }
+
+    #[test]
+    fn empty_yaml_is_an_empty_table() {
+        assert_eq!(ConfigFormat::Yaml.parse("").unwrap(), toml::Table::new());
+    }
+
+    #[test]
+    fn invalid_yaml_fails() {
+        assert!(ConfigFormat::Yaml.parse("papers: [QP").is_err());
+        assert!(ConfigFormat::Yaml.parse("papers: [QP, ~]").is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use std::{fs::File, io::Write, path::PathBuf, vec};
+use std::{path::PathBuf, str::FromStr, vec};
 
+use clap::ValueEnum;
 use futures::StreamExt;
 
 use crate::{
-    configuration::{Configuration, PaperType, RawPaper, Season, SyllabusCode, YearConfiguration, SYLLABUS_CODES},
+    config_format::{write_atomically, ConfigFormat},
+    config_migrate::migrate,
+    configuration::{Configuration, CONFIG_VERSION, Paper, PaperType, RawPaper, Season, SyllabusCode, YearConfiguration, YearRange, SYLLABUS_CODES},
     scraper::{get_all_papers, get_all_years, PaperRequest},
 };
 #[derive(Debug)]
@@ -16,125 +19,450 @@ pub struct PaperGenerationConfig {
 
 #[derive(Debug)]
 pub struct GenerationConfig {
-    output: File,
+    output: PathBuf,
+    format: ConfigFormat,
     paper_generation_config: PaperGenerationConfig,
     threads: u8,
+    /// Merge into the existing configuration instead of replacing it.
+    update: bool,
+    /// How many of the latest published years are fetched again when updating.
+    recent: u8,
 }
 
 impl GenerationConfig {
     pub fn new(
         output: PathBuf,
+        format: Option<ConfigFormat>,
         paper_generation_config: PaperGenerationConfig,
         threads: u8,
+        update: bool,
+        recent: u8,
     ) -> Self {
-        let output = match File::create(output) {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to create configuration file: {}", e);
-                std::process::exit(1);
-            }
-        };
+        let format = ConfigFormat::resolve(format, &output);
         Self {
             output,
+            format,
             paper_generation_config,
             threads,
+            update,
+            recent,
         }
     }
 }
 
-pub fn handle_generate(mut config: GenerationConfig) {
-    info!("Generating configuration file at {:?}", config.output);
-    debug!("Configuration: {:?}", config);
+pub fn runtime(threads: u8) -> tokio::runtime::Runtime {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(threads as usize)
+        .enable_all()
+        .build();
 
-    // Generate the configuration file
+    match rt {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("Failed to create tokio runtime: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn find_subjects(subjects: &[String]) -> Vec<SyllabusCode> {
+    subjects
+        .iter()
+        .filter_map(|y| {
+            let code = SyllabusCode::find(y);
+            if code.is_none() {
+                error!("Invalid subject code: {}", y);
+                std::process::exit(1);
+            }
+            code
+        })
+        .collect()
+}
+
+/// The syllabi a selection names, every one when it names none.
+pub fn selected_subjects(paper_generation_config: &PaperGenerationConfig) -> Vec<SyllabusCode> {
+    match &paper_generation_config.subjects {
+        Some(subjects) => find_subjects(subjects),
+        None => SYLLABUS_CODES.clone(),
+    }
+}
+
+/// Fetches the papers of `syllabus_codes` a selection covers, the configuration `generate-config` writes.
+/// The subjects of the selection are not looked up again, `syllabus_codes` replaces them.
+pub fn generate(
+    syllabus_codes: Vec<SyllabusCode>,
+    paper_generation_config: PaperGenerationConfig,
+    threads: u8,
+) -> Configuration {
     let mut f_config = Configuration {
-        papers: config.paper_generation_config.papers.clone(),
+        version: CONFIG_VERSION,
+        papers: paper_generation_config.papers.clone(),
+        rules: vec![],
         subjects: vec![],
+        layout: None,
+        bandwidth: None,
+        windows: vec![],
     };
 
-    let seasons = config.paper_generation_config.seasons.unwrap_or(vec![
+    let seasons = paper_generation_config.seasons.unwrap_or(vec![
         Season::March,
         Season::Summer,
         Season::Winter,
     ]);
 
-    let syllabus_codes: Vec<SyllabusCode> = config
-        .paper_generation_config
+    let rt = runtime(threads);
+
+    let years = match paper_generation_config.years {
+        Some(years) => YearSelection::Listed(years),
+        None => YearSelection::Range(YearRange::default()),
+    };
+    let failed;
+    (f_config.subjects, failed) = rt.block_on(fetch_papers(
+        syllabus_codes,
+        years,
+        seasons,
+        paper_generation_config.papers.clone(),
+        threads,
+    ));
+    if failed > 0 {
+        warn!("{} listings could not be fetched, their papers are missing.", failed);
+    }
+    for subject in f_config.subjects.iter_mut() {
+        subject.known = Some(known_names(&subject.syllabus_code, &subject.papers, &[]));
+    }
+    f_config
+}
+
+/// Writes a generated configuration, replacing the file.
+pub fn write_configuration(output: &std::path::Path, format: ConfigFormat, f_config: &Configuration) {
+    let serialized = format.serialize(f_config).unwrap();
+
+    debug!("Writing {} bytes to configuration file.", serialized.len());
+    // Nothing is written until every listing has been fetched.
+    match write_atomically(output, &serialized) {
+        Ok(_) => {
+            info!("Configuration file generated successfully.");
+        }
+        Err(e) => {
+            error!("Failed to write to configuration file: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The `known` list of a subject: the names already recorded, then those of `papers` not among them.
+fn known_names(syllabus_code: &SyllabusCode, papers: &[Paper], recorded: &[String]) -> Vec<String> {
+    let mut known = recorded.to_vec();
+    for paper in papers {
+        let name = paper.get_ref_filename(syllabus_code);
+        if !known.contains(&name) {
+            known.push(name);
+        }
+    }
+    known
+}
+
+pub fn handle_generate(config: GenerationConfig) {
+    if config.update {
+        return handle_update(config);
+    }
+    info!("Generating configuration file at {:?}", config.output);
+    debug!("Configuration: {:?}", config);
+
+    // Generate the configuration file
+    let syllabus_codes = selected_subjects(&config.paper_generation_config);
+    let f_config = generate(syllabus_codes, config.paper_generation_config, config.threads);
+    if f_config.subjects.is_empty() {
+        error!("No papers found.");
+        std::process::exit(1);
+    }
+    write_configuration(&config.output, config.format, &f_config);
+}
+
+/// Adds the papers of missing or recent years to an existing configuration.
+/// Papers recorded as known but no longer listed were removed by hand and stay removed. Subjects written before
+/// known papers were recorded only get sessions without any papers filled in, then start recording.
+/// One line per configured paper, e.g. "Mathematics (9709) 9709_s23_qp_12.pdf", to tell what an update changed.
+fn paper_lines(config: &Configuration) -> Vec<String> {
+    config
         .subjects
-        .map(|x| {
-            x.iter()
-                .filter_map(|y| {
-                    let code = SYLLABUS_CODES
-                        .iter()
-                        .find(|&code| {
-                            code.name.to_lowercase().starts_with(&y.to_lowercase())
-                                || code
-                                    .syllabus_code
-                                    .to_lowercase()
-                                    .starts_with(&y.to_lowercase())
-                        })
-                        .cloned();
-                    if code.is_none() {
-                        error!("Invalid subject code: {}", y);
-                        std::process::exit(1);
-                    }
-                    code
-                })
-                .collect()
+        .iter()
+        .flat_map(|subject| {
+            subject.papers.iter().map(|paper| {
+                format!(
+                    "{} ({}) {}",
+                    subject.syllabus_code.name,
+                    subject.syllabus_code.syllabus_code,
+                    paper.get_ref_filename(&subject.syllabus_code)
+                )
+            })
         })
-        .unwrap_or(SYLLABUS_CODES.clone());
-
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(config.threads as usize)
-        .enable_all()
-        .build();
+        .collect()
+}
 
-    let rt = match rt {
-        Ok(rt) => rt,
+fn handle_update(config: GenerationConfig) {
+    info!("Updating configuration file at {:?}", config.output);
+    let content = match std::fs::read_to_string(&config.output) {
+        Ok(content) => content,
         Err(e) => {
-            error!("Failed to create tokio runtime: {}", e);
+            error!(
+                "Failed to read configuration file {:?}, generate it without --update first: {}",
+                config.output, e
+            );
+            std::process::exit(1);
+        }
+    };
+    let raw = config
+        .format
+        .parse(&content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        .and_then(migrate);
+    let raw = match raw {
+        Ok(raw) => raw,
+        Err(e) => {
+            error!("Error parsing configuration file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut existing = match Configuration::parse(&content, config.format) {
+        Ok(existing) => existing,
+        Err(e) => {
+            error!("Error parsing configuration file: {}", e);
             std::process::exit(1);
         }
     };
 
-    let raw_papers = syllabus_codes.iter().map(|x| RawPaper {
-        year: config
-            .paper_generation_config
-            .years
-            .clone()
-            .unwrap_or_default(),
-        syllabus_code: x.clone(),
-    });
-    let raw_papers = rt.block_on(async {
-        futures::stream::iter(raw_papers)
-            .map(|paper| async move {
-                if !paper.year.is_empty() {
-                    return Some(paper);
+    let pgc = &config.paper_generation_config;
+    let syllabus_codes = match &pgc.subjects {
+        Some(subjects) => find_subjects(subjects),
+        None => {
+            let mut codes: Vec<SyllabusCode> = vec![];
+            for subject in &existing.subjects {
+                if !codes.iter().any(|x| x.access_slug == subject.syllabus_code.access_slug) {
+                    codes.push(subject.syllabus_code.clone());
                 }
-                let years = get_all_years(&paper.syllabus_code).await;
-                match years {
-                    Ok(years) => Some(RawPaper {
-                        year: years,
-                        syllabus_code: paper.syllabus_code,
-                    }),
-                    Err(e) => {
-                        error!(
-                            "Failed to fetch years for {}: {:?}",
-                            paper.syllabus_code.name, e
-                        );
-                        None
+            }
+            codes
+        }
+    };
+    // Keep to what the configuration already selects, an empty selection fetches every type.
+    let papers = existing.selected_paper_types();
+    let mut seasons: Vec<Season> = vec![];
+    for paper in existing.subjects.iter().flat_map(|x| x.papers.iter()) {
+        if !seasons.contains(&paper.season) {
+            seasons.push(paper.season.clone());
+        }
+    }
+    if seasons.is_empty() {
+        seasons = pgc
+            .seasons
+            .clone()
+            .unwrap_or(vec![Season::March, Season::Summer, Season::Winter]);
+    }
+
+    let rt = runtime(config.threads);
+    let before = paper_lines(&existing);
+    let mut failed = 0;
+    // Known papers are saved even when nothing was added.
+    let mut recorded_changed = false;
+    for syllabus_code in syllabus_codes {
+        let mut listed = match rt.block_on(get_all_years(&syllabus_code)) {
+            Ok(years) => years,
+            Err(e) => {
+                error!("Failed to fetch years for {}: {:?}", syllabus_code.name, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(years) = &pgc.years {
+            listed.retain(|x| years.contains(x));
+        }
+        listed.sort_by(|a, b| b.cmp(a));
+
+        let index = existing
+            .subjects
+            .iter()
+            .position(|x| x.syllabus_code.access_slug == syllabus_code.access_slug);
+        let present = index
+            .map(|i| existing.subjects[i].papers.clone())
+            .unwrap_or_default();
+        let recorded = index.and_then(|i| existing.subjects[i].known.clone());
+        let known = recorded
+            .iter()
+            .flatten()
+            .filter_map(|x| Paper::from_str(x).ok())
+            .collect::<Vec<_>>();
+        // Years whose papers were all removed by hand are known, they aren't fetched again.
+        let years = listed
+            .iter()
+            .enumerate()
+            .filter(|(i, year)| {
+                *i < config.recent as usize
+                    || !present.iter().chain(known.iter()).any(|x| &x.year == *year)
+            })
+            .map(|(_, year)| year.clone())
+            .collect::<Vec<_>>();
+        if years.is_empty() {
+            debug!("{} is up to date.", syllabus_code.name);
+            continue;
+        }
+        debug!("Fetching {:?} for {}.", years, syllabus_code.name);
+
+        let (fetched, listings_failed) = rt.block_on(fetch_papers(
+            vec![syllabus_code.clone()],
+            YearSelection::Listed(years),
+            seasons.clone(),
+            papers.clone(),
+            config.threads,
+        ));
+        if listings_failed > 0 {
+            failed += 1;
+        }
+        let listed_papers = fetched
+            .into_iter()
+            .flat_map(|x| x.papers)
+            .filter(|paper| seasons.contains(&paper.season))
+            .collect::<Vec<_>>();
+        let new_papers = listed_papers
+            .iter()
+            .filter(|paper| {
+                !present.contains(paper)
+                    && match &recorded {
+                        Some(_) => !known.contains(paper),
+                        None => !present.iter().any(|x| x.year == paper.year && x.season == paper.season),
                     }
-                }
             })
-            .buffer_unordered(config.threads as usize)
-            .collect::<Vec<_>>()
-            .await
-    });
-    let raw_papers = raw_papers.into_iter().flatten().collect::<Vec<_>>();
-    if raw_papers.is_empty() {
-        error!("No papers found.");
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut all_papers = present.clone();
+        all_papers.extend(listed_papers);
+        let known = known_names(&syllabus_code, &all_papers, recorded.as_deref().unwrap_or_default());
+        if recorded.as_ref() != Some(&known) {
+            recorded_changed = true;
+        }
+        match index {
+            Some(i) => {
+                existing.subjects[i].papers.extend(new_papers);
+                existing.subjects[i].known = Some(known);
+            }
+            None if new_papers.is_empty() => {}
+            None => existing.subjects.push(YearConfiguration {
+                syllabus_code,
+                paper_types: None,
+                papers: new_papers,
+                known: Some(known),
+            }),
+        }
+    }
+
+    let after = paper_lines(&existing);
+    let removed = before.iter().filter(|x| !after.contains(x)).collect::<Vec<_>>();
+    let added = after.iter().filter(|x| !before.contains(x)).collect::<Vec<_>>();
+    if added.is_empty() && removed.is_empty() && failed > 0 {
+        error!("No papers added, {} subjects could not be fetched.", failed);
         std::process::exit(1);
     }
+    if added.is_empty() && removed.is_empty() && !recorded_changed {
+        info!("Configuration is up to date.");
+        return;
+    }
+
+    // Fields the model doesn't hold, such as includes and profiles, are carried over untouched.
+    let mut updated = match toml::Value::try_from(&existing) {
+        Ok(toml::Value::Table(updated)) => updated,
+        _ => {
+            error!("Failed to serialize configuration.");
+            std::process::exit(1);
+        }
+    };
+    for (key, value) in raw {
+        updated.entry(key).or_insert(value);
+    }
+    let serialized = match config.format.serialize(&updated) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            error!("Failed to serialize configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = write_atomically(&config.output, &serialized) {
+        error!("Failed to write to configuration file: {}", e);
+        std::process::exit(1);
+    }
+    // The papers of the written configuration against those it was read with.
+    for line in &removed {
+        println!("- {}", line);
+    }
+    for line in &added {
+        println!("+ {}", line);
+    }
+    match (added.len(), removed.len()) {
+        (0, 0) => info!("Configuration is up to date, recorded the papers listed for it."),
+        (x, 0) => info!("Added {} papers to {:?}.", x, config.output),
+        (x, y) => info!("Added {} and removed {} papers in {:?}.", x, y, config.output),
+    }
+    if failed > 0 {
+        warn!("{} subjects could not be fetched and were left as they were.", failed);
+    }
+}
+
+/// Which years of a syllabus to fetch papers for.
+#[derive(Debug, Clone)]
+pub enum YearSelection {
+    /// Exactly these years, the year listing isn't requested.
+    Listed(Vec<String>),
+    /// Every published year inside the range.
+    Range(YearRange),
+}
+
+/// Fetches the paper listings of every syllabus, one `YearConfiguration` per syllabus.
+/// Listings that can't be fetched are logged and skipped, their number is returned alongside.
+/// No paper types selects every type, as an empty `papers` does in a configuration.
+pub async fn fetch_papers(
+    syllabus_codes: Vec<SyllabusCode>,
+    years: YearSelection,
+    seasons: Vec<Season>,
+    papers: Vec<PaperType>,
+    threads: u8,
+) -> (Vec<YearConfiguration>, usize) {
+    let papers = match papers.is_empty() {
+        true => PaperType::value_variants().to_vec(),
+        false => papers,
+    };
+    let raw_papers = syllabus_codes.into_iter().map(|x| RawPaper {
+        year: match &years {
+            YearSelection::Listed(years) => years.clone(),
+            YearSelection::Range(_) => vec![],
+        },
+        syllabus_code: x,
+    });
+    let years = &years;
+    let raw_papers = futures::stream::iter(raw_papers)
+        .map(|paper| async move {
+            let range = match years {
+                YearSelection::Listed(_) => return Ok(paper),
+                YearSelection::Range(range) => range,
+            };
+            let years = get_all_years(&paper.syllabus_code).await;
+            match years {
+                Ok(years) => Ok(RawPaper {
+                    year: years.into_iter().filter(|x| range.contains(x)).collect(),
+                    syllabus_code: paper.syllabus_code,
+                }),
+                Err(e) => {
+                    error!(
+                        "Failed to fetch years for {}: {:?}",
+                        paper.syllabus_code.name, e
+                    );
+                    Err(e)
+                }
+            }
+        })
+        .buffer_unordered(threads as usize)
+        .collect::<Vec<_>>()
+        .await;
+    let mut failed = raw_papers.iter().filter(|x| x.is_err()).count();
+    let raw_papers = raw_papers.into_iter().flatten().collect::<Vec<_>>();
 
     let paper_request = raw_papers.iter().flat_map(|paper| {
         paper.year.iter().map(|year| {
@@ -142,46 +470,40 @@ pub fn handle_generate(mut config: GenerationConfig) {
                 syllabus: paper.syllabus_code.clone(),
                 year: year.clone(),
                 seasons: seasons.clone(),
-                papers: config.paper_generation_config.papers.clone(),
+                papers: papers.clone(),
             }
         })
     }).collect::<Vec<_>>();
 
-    // async block
-    let papers = rt.block_on(async {
-        futures::stream::iter(paper_request)
-            .map(|request| async move {
-                let papers = get_all_papers(&request).await;
-                if papers.is_empty() {
-                    error!("No papers found for {:?}", request);
-                }
-                YearConfiguration {
-                    papers,
-                    syllabus_code: request.syllabus.clone()
-                }
+    let papers = futures::stream::iter(paper_request)
+        .map(|request| async move {
+            let papers = get_all_papers(&request).await;
+            if papers.as_ref().is_ok_and(|x| x.is_empty()) {
+                error!("No papers found for {:?}", request);
+            }
+            papers.map(|papers| YearConfiguration {
+                paper_types: None,
+                papers,
+                syllabus_code: request.syllabus.clone(),
+                known: None,
             })
-            .buffer_unordered(config.threads as usize)
-            .collect::<Vec<_>>()
-            .await
-    }).into_iter().collect::<Vec<_>>();
-    
-
-    f_config.subjects = papers;
-    
-    let toml_config = toml::to_string(&f_config).unwrap();
-
-    // as_bytes() is cheap.
-    debug!(
-        "Writing {} bytes to configuration file.",
-        toml_config.as_bytes().len()
-    );
-    match config.output.write_all(toml_config.as_bytes()) {
-        Ok(_) => {
-            info!("Configuration file generated successfully.");
-        }
-        Err(e) => {
-            error!("Failed to write to configuration file: {}", e);
-            std::process::exit(1);
+        })
+        .buffer_unordered(threads as usize)
+        .collect::<Vec<_>>()
+        .await;
+    failed += papers.iter().filter(|x| x.is_err()).count();
+    let papers = papers.into_iter().flatten();
+
+    // One entry per syllabus, the years were fetched separately.
+    let mut subjects: Vec<YearConfiguration> = vec![];
+    for year_config in papers {
+        match subjects
+            .iter_mut()
+            .find(|x| x.syllabus_code.access_slug == year_config.syllabus_code.access_slug)
+        {
+            Some(existing) => existing.papers.extend(year_config.papers),
+            None => subjects.push(year_config),
         }
     }
+    (subjects, failed)
 }
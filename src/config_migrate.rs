@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use toml::{Table, Value};
+
+use crate::{
+    config_format::{write_atomically, ConfigFormat},
+    configuration::{Configuration, CONFIG_VERSION},
+};
+
+#[derive(Debug)]
+pub struct MigrateConfiguration {
+    pub config: PathBuf,
+    pub output: Option<PathBuf>,
+    pub format: Option<ConfigFormat>,
+}
+
+fn newer_version(version: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "Configuration version {} is newer than the supported version {}, please update gce-scraper.",
+            version, CONFIG_VERSION
+        ),
+    )
+}
+
+/// Reads the schema version of a raw configuration. Files written before versioning have none and are version 1.
+pub fn schema_version(config: &Table) -> Result<u32, std::io::Error> {
+    match config.get("version") {
+        None => Ok(1),
+        Some(Value::Integer(version)) if *version >= 1 => u32::try_from(*version).map_err(|_| newer_version(version)),
+        Some(version) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid configuration version: {}", version),
+        )),
+    }
+}
+
+/// Version 1 wrote one subject entry per syllabus and year, version 2 keeps one entry per syllabus.
+fn migrate_v1(config: &mut Table) {
+    let subjects = match config.get_mut("subjects").and_then(Value::as_array_mut) {
+        Some(subjects) => subjects,
+        None => return,
+    };
+    let mut merged: Vec<Value> = vec![];
+    for subject in subjects.drain(..) {
+        let code = subject
+            .get("syllabus_code")
+            .and_then(|x| x.get("access_slug"))
+            .cloned();
+        let existing = merged.iter_mut().find(|x| {
+            code.is_some() && x.get("syllabus_code").and_then(|y| y.get("access_slug")) == code.as_ref()
+        });
+        match (existing, subject.get("papers").and_then(Value::as_array)) {
+            (Some(existing), Some(papers)) => {
+                if let Some(existing) = existing.get_mut("papers").and_then(Value::as_array_mut) {
+                    existing.extend(papers.iter().cloned());
+                }
+            }
+            _ => merged.push(subject),
+        }
+    }
+    *subjects = merged;
+}
+
+/// Upgrades a raw configuration of any known schema version to `CONFIG_VERSION`.
+pub fn migrate(mut config: Table) -> Result<Table, std::io::Error> {
+    let mut version = schema_version(&config)?;
+    if version > CONFIG_VERSION {
+        return Err(newer_version(version));
+    }
+    while version < CONFIG_VERSION {
+        debug!("Migrating configuration from version {}.", version);
+        if version == 1 {
+            migrate_v1(&mut config);
+        }
+        version += 1;
+    }
+    config.insert("version".to_string(), Value::Integer(CONFIG_VERSION as i64));
+    Ok(config)
+}
+
+pub fn handle_migrate(config: MigrateConfiguration) {
+    // Migrate the raw file, so includes and profiles are kept rather than merged in.
+    let input = ConfigFormat::resolve(config.format, &config.config);
+    let migrated = std::fs::read_to_string(&config.config).and_then(|content| {
+        Configuration::parse(&content, input)?;
+        input
+            .parse(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            .and_then(migrate)
+    });
+    let migrated = match migrated {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            error!("Error parsing configuration file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Rewriting in place keeps the input format, a separate output follows its own extension.
+    let format = match &config.output {
+        Some(output) => ConfigFormat::from_path(output),
+        None => input,
+    };
+    let output = config.output.unwrap_or(config.config);
+    let serialized = format.serialize(&migrated).unwrap();
+    match write_atomically(&output, &serialized) {
+        Ok(_) => info!(
+            "Configuration migrated to version {} at {:?}",
+            CONFIG_VERSION, output
+        ),
+        Err(e) => {
+            error!("Failed to write configuration file: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
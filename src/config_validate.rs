@@ -0,0 +1,1102 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use clap::ValueEnum;
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::{Marker, TScalarStyle},
+};
+
+use crate::{
+    config_format::ConfigFormat,
+    configuration::{
+        Configuration, Paper, PaperType, Season, SelectionRule, SyllabusCode, YearConfiguration, YearRange,
+        CONFIG_VERSION, SYLLABUS_CODES,
+    },
+    layout::Layout,
+    throttle::{Rate, Window},
+};
+
+#[derive(Debug)]
+pub struct ValidateConfiguration {
+    pub config: PathBuf,
+    pub format: Option<ConfigFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a configuration file, positioned at the offending value.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based line.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}:{}: {}: {}", self.line, self.column, severity, self.message)?;
+        if let Some(help) = &self.help {
+            write!(f, " ({})", help)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed configuration value remembering where it was written.
+#[derive(Debug)]
+struct Node {
+    value: NodeValue,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum NodeValue {
+    Table(Vec<Entry>),
+    Array(Vec<Node>),
+    String(String),
+    /// Numbers, booleans and dates, kept in their written form.
+    Other(String),
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    line: usize,
+    column: usize,
+    value: Node,
+}
+
+impl Node {
+    fn describe(&self) -> &'static str {
+        match self.value {
+            NodeValue::Table(_) => "a table",
+            NodeValue::Array(_) => "an array",
+            NodeValue::String(_) => "a string",
+            NodeValue::Other(_) => "a number or boolean",
+        }
+    }
+}
+
+/// Converts byte offsets into 1-based line and character column.
+struct LineIndex<'a> {
+    content: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(content: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { content, starts }
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|x| *x <= offset).max(1);
+        let start = self.starts[line - 1];
+        let column = self
+            .content
+            .get(start..offset.min(self.content.len()))
+            .map(|x| x.chars().count())
+            .unwrap_or(0);
+        (line, column + 1)
+    }
+}
+
+fn toml_position(
+    index: &LineIndex,
+    span: Option<std::ops::Range<usize>>,
+    fallback: (usize, usize),
+) -> (usize, usize) {
+    span.map(|x| index.position(x.start)).unwrap_or(fallback)
+}
+
+fn toml_value(index: &LineIndex, value: &toml_edit::Value, fallback: (usize, usize)) -> Node {
+    let (line, column) = toml_position(index, value.span(), fallback);
+    let value = match value {
+        toml_edit::Value::String(x) => NodeValue::String(x.value().clone()),
+        toml_edit::Value::Array(array) => NodeValue::Array(
+            array
+                .iter()
+                .map(|x| toml_value(index, x, (line, column)))
+                .collect(),
+        ),
+        toml_edit::Value::InlineTable(table) => NodeValue::Table(
+            table
+                .iter()
+                .map(|(key, value)| {
+                    let (key_line, key_column) =
+                        toml_position(index, table.key(key).and_then(|x| x.span()), (line, column));
+                    Entry {
+                        key: key.to_string(),
+                        line: key_line,
+                        column: key_column,
+                        value: toml_value(index, value, (key_line, key_column)),
+                    }
+                })
+                .collect(),
+        ),
+        other => NodeValue::Other(other.to_string().trim().to_string()),
+    };
+    Node { value, line, column }
+}
+
+fn toml_table(index: &LineIndex, table: &toml_edit::Table, fallback: (usize, usize)) -> Node {
+    let (line, column) = toml_position(index, table.span(), fallback);
+    let entries = table
+        .iter()
+        .map(|(key, item)| {
+            let (key_line, key_column) =
+                toml_position(index, table.key(key).and_then(|x| x.span()), (line, column));
+            Entry {
+                key: key.to_string(),
+                line: key_line,
+                column: key_column,
+                value: toml_item(index, item, (key_line, key_column)),
+            }
+        })
+        .collect();
+    Node {
+        value: NodeValue::Table(entries),
+        line,
+        column,
+    }
+}
+
+fn toml_item(index: &LineIndex, item: &toml_edit::Item, fallback: (usize, usize)) -> Node {
+    match item {
+        toml_edit::Item::Value(value) => toml_value(index, value, fallback),
+        toml_edit::Item::Table(table) => toml_table(index, table, fallback),
+        toml_edit::Item::ArrayOfTables(tables) => Node {
+            value: NodeValue::Array(tables.iter().map(|x| toml_table(index, x, fallback)).collect()),
+            line: fallback.0,
+            column: fallback.1,
+        },
+        toml_edit::Item::None => Node {
+            value: NodeValue::Other(String::new()),
+            line: fallback.0,
+            column: fallback.1,
+        },
+    }
+}
+
+fn parse_toml(content: &str) -> Result<Node, Diagnostic> {
+    let index = LineIndex::new(content);
+    match toml_edit::ImDocument::parse(content) {
+        Ok(document) => Ok(toml_table(&index, document.as_table(), (1, 1))),
+        Err(e) => {
+            let (line, column) = toml_position(&index, e.span(), (1, 1));
+            let message = e.message().trim().lines().collect::<Vec<_>>().join(", ");
+            Err(error(line, column, message, None))
+        }
+    }
+}
+
+enum Frame {
+    Array(Node),
+    Table(Node, Option<(String, usize, usize)>),
+}
+
+/// Builds a positioned tree out of the YAML event stream. JSON documents are valid YAML.
+#[derive(Default)]
+struct YamlBuilder {
+    stack: Vec<Frame>,
+    root: Option<Node>,
+    error: Option<Diagnostic>,
+}
+
+impl YamlBuilder {
+    fn insert(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some(Frame::Array(array)) => {
+                if let NodeValue::Array(items) = &mut array.value {
+                    items.push(node);
+                }
+            }
+            Some(Frame::Table(table, key)) => match key.take() {
+                None => match node.value {
+                    NodeValue::String(x) | NodeValue::Other(x) => *key = Some((x, node.line, node.column)),
+                    _ => {
+                        self.error.get_or_insert(error(
+                            node.line,
+                            node.column,
+                            "Keys must be plain strings".to_string(),
+                            None,
+                        ));
+                    }
+                },
+                Some((key, line, column)) => {
+                    if let NodeValue::Table(entries) = &mut table.value {
+                        entries.push(Entry {
+                            key,
+                            line,
+                            column,
+                            value: node,
+                        });
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for YamlBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let (line, column) = (mark.line(), mark.col() + 1);
+        match ev {
+            Event::Scalar(value, style, _, _) => {
+                let value = match style {
+                    TScalarStyle::Plain
+                        if value.parse::<f64>().is_ok()
+                            || ["true", "false", "null", "~", ""].contains(&value.as_str()) =>
+                    {
+                        NodeValue::Other(value)
+                    }
+                    _ => NodeValue::String(value),
+                };
+                self.insert(Node { value, line, column });
+            }
+            Event::SequenceStart(..) => self.stack.push(Frame::Array(Node {
+                value: NodeValue::Array(vec![]),
+                line,
+                column,
+            })),
+            Event::MappingStart(..) => self.stack.push(Frame::Table(
+                Node {
+                    value: NodeValue::Table(vec![]),
+                    line,
+                    column,
+                },
+                None,
+            )),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some(Frame::Array(node) | Frame::Table(node, _)) = self.stack.pop() {
+                    self.insert(node);
+                }
+            }
+            Event::Alias(_) => {
+                self.error.get_or_insert(error(
+                    line,
+                    column,
+                    "Aliases are not supported in configuration files".to_string(),
+                    None,
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_yaml(content: &str) -> Result<Node, Diagnostic> {
+    let mut builder = YamlBuilder::default();
+    if let Err(e) = Parser::new_from_str(content).load(&mut builder, false) {
+        return Err(error(e.marker().line(), e.marker().col() + 1, e.info().to_string(), None));
+    }
+    if let Some(e) = builder.error {
+        return Err(e);
+    }
+    Ok(builder.root.unwrap_or(Node {
+        value: NodeValue::Table(vec![]),
+        line: 1,
+        column: 1,
+    }))
+}
+
+fn error(line: usize, column: usize, message: String, help: Option<String>) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        line,
+        column,
+        message,
+        help,
+    }
+}
+
+fn warning(line: usize, column: usize, message: String, help: Option<String>) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        line,
+        column,
+        message,
+        help,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = (previous + (x != *y) as usize).min(row[j] + 1).min(current + 1);
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate `value` was most likely meant to be, if any is close enough.
+fn closest<'a>(value: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let lower = value.to_lowercase();
+    candidates
+        .into_iter()
+        .map(|x| (edit_distance(&lower, &x.to_lowercase()), x))
+        .filter(|(distance, _)| *distance <= (value.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x)
+}
+
+/// The keys serde reads for `T`, taken from its derived `Deserialize` so new fields are accepted without being listed here.
+fn model_keys<T: serde::de::DeserializeOwned>() -> &'static [&'static str] {
+    use serde::de::{Error, Visitor};
+
+    struct Keys<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for Keys<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(Error::custom("only the keys are read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut keys: &'static [&'static str] = &[];
+    let _ = T::deserialize(Keys(&mut keys));
+    keys
+}
+
+fn did_you_mean(value: &str, candidates: &[&str]) -> String {
+    match closest(value, candidates.iter().copied()) {
+        Some(x) => format!("did you mean \"{}\"?", x),
+        None => format!("expected one of {}", candidates.join(", ")),
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    /// Included files with the position they are named at.
+    includes: Vec<(String, usize, usize)>,
+}
+
+impl Validator {
+    fn table<'a>(&mut self, node: &'a Node, what: &str) -> Option<&'a [Entry]> {
+        match &node.value {
+            NodeValue::Table(entries) => Some(entries),
+            _ => {
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("{} must be a table, found {}", what, node.describe()),
+                    None,
+                ));
+                None
+            }
+        }
+    }
+
+    fn array<'a>(&mut self, node: &'a Node, what: &str) -> Option<&'a [Node]> {
+        match &node.value {
+            NodeValue::Array(items) => Some(items),
+            _ => {
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("{} must be an array, found {}", what, node.describe()),
+                    None,
+                ));
+                None
+            }
+        }
+    }
+
+    fn string<'a>(&mut self, node: &'a Node, what: &str) -> Option<&'a str> {
+        match &node.value {
+            NodeValue::String(x) => Some(x),
+            NodeValue::Other(x) => {
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("{} must be a string, found {}", what, x),
+                    Some(format!("quote it: \"{}\"", x)),
+                ));
+                None
+            }
+            _ => {
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("{} must be a string, found {}", what, node.describe()),
+                    None,
+                ));
+                None
+            }
+        }
+    }
+
+    /// Reports keys outside `allowed` and returns the value of every `known` key, missing required keys are reported at `node`.
+    fn fields<'a, const N: usize>(
+        &mut self,
+        node: &'a Node,
+        entries: &'a [Entry],
+        what: &str,
+        allowed: &[&str],
+        known: [&str; N],
+        required: &[&str],
+    ) -> [Option<&'a Node>; N] {
+        let mut found: [Option<&Node>; N] = [None; N];
+        let mut seen = Vec::new();
+        for entry in entries {
+            if !allowed.contains(&entry.key.as_str()) {
+                self.diagnostics.push(error(
+                    entry.line,
+                    entry.column,
+                    format!("Unknown key \"{}\" in {}", entry.key, what),
+                    Some(did_you_mean(&entry.key, allowed)),
+                ));
+            } else if seen.contains(&entry.key) {
+                self.diagnostics.push(error(
+                    entry.line,
+                    entry.column,
+                    format!("Duplicate key \"{}\" in {}", entry.key, what),
+                    None,
+                ));
+            } else {
+                seen.push(entry.key.clone());
+                if let Some(i) = known.iter().position(|x| *x == entry.key) {
+                    found[i] = Some(&entry.value);
+                }
+            }
+        }
+        for key in required {
+            if let Some(i) = known.iter().position(|x| x == key) {
+                if found[i].is_none() {
+                    self.diagnostics.push(error(
+                        node.line,
+                        node.column,
+                        format!("Missing key \"{}\" in {}", key, what),
+                        None,
+                    ));
+                }
+            }
+        }
+        found
+    }
+
+    fn variant<T: ValueEnum + std::fmt::Debug>(&mut self, node: &Node, what: &str) -> Option<T> {
+        let value = self.string(node, what)?;
+        // Serde writes the variant names.
+        let names = T::value_variants().iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>();
+        match names.iter().position(|x| x == value) {
+            Some(i) => Some(T::value_variants()[i].clone()),
+            None => {
+                let candidates = names.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("Unknown {} \"{}\"", what, value),
+                    Some(did_you_mean(value, &candidates)),
+                ));
+                None
+            }
+        }
+    }
+
+    fn year(&mut self, node: &Node) -> Option<String> {
+        if let NodeValue::Other(x) = &node.value {
+            if x.len() == 4 && x.chars().all(|y| y.is_ascii_digit()) {
+                self.diagnostics.push(error(
+                    node.line,
+                    node.column,
+                    format!("Year {} must be a string", x),
+                    Some(format!("quote it: \"{}\"", x)),
+                ));
+                return None;
+            }
+        }
+        let year = self.string(node, "year")?;
+        if year.len() != 4 || !year.chars().all(|x| x.is_ascii_digit()) {
+            self.diagnostics.push(error(
+                node.line,
+                node.column,
+                format!("Malformed year \"{}\"", year),
+                Some("years are written with four digits, e.g. \"2023\"".to_string()),
+            ));
+            return None;
+        }
+        Some(year.to_string())
+    }
+
+    fn unknown_subject(&mut self, node: &Node, value: &str) {
+        let candidates = SYLLABUS_CODES
+            .iter()
+            .flat_map(|x| [x.name.as_str(), x.syllabus_code.as_str()]);
+        let help = closest(value, candidates).map(|x| match SyllabusCode::find(x) {
+            Some(code) => format!("did you mean {} ({})?", code.syllabus_code, code.name),
+            None => format!("did you mean \"{}\"?", x),
+        });
+        self.diagnostics.push(error(
+            node.line,
+            node.column,
+            format!("Unknown syllabus code \"{}\"", value),
+            help,
+        ));
+    }
+
+    fn root(&mut self, node: &Node) {
+        let entries = match self.table(node, "The configuration") {
+            Some(entries) => entries,
+            None => return,
+        };
+        let [version, include, profile, papers, rules, subjects, layout, bandwidth, windows] = self.fields(
+            node,
+            entries,
+            "the configuration",
+            &[model_keys::<Configuration>(), &["include", "profile"]].concat(),
+            [
+                "version", "include", "profile", "papers", "rules", "subjects", "layout", "bandwidth", "windows",
+            ],
+            &[],
+        );
+        // Version 1 wrote one entry per year, migration merges them.
+        let merged_on_load = version.is_none_or(|x| matches!(&x.value, NodeValue::Other(y) if y == "1"));
+        if let Some(version) = version {
+            match &version.value {
+                NodeValue::Other(x) => match x.parse::<u32>() {
+                    Ok(x) if x > CONFIG_VERSION => self.diagnostics.push(error(
+                        version.line,
+                        version.column,
+                        format!(
+                            "Configuration version {} is newer than the supported version {}",
+                            x, CONFIG_VERSION
+                        ),
+                        Some("please update gce-scraper".to_string()),
+                    )),
+                    Ok(x) if x >= 1 => {}
+                    _ => self.diagnostics.push(error(
+                        version.line,
+                        version.column,
+                        format!("Invalid configuration version {}", x),
+                        None,
+                    )),
+                },
+                _ => self.diagnostics.push(error(
+                    version.line,
+                    version.column,
+                    format!("version must be a number, found {}", version.describe()),
+                    None,
+                )),
+            }
+        }
+        if let Some(include) = include {
+            match &include.value {
+                NodeValue::Array(items) => {
+                    for item in items {
+                        if let Some(x) = self.string(item, "include") {
+                            self.includes.push((x.to_string(), item.line, item.column));
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(x) = self.string(include, "include") {
+                        self.includes.push((x.to_string(), include.line, include.column));
+                    }
+                }
+            }
+        }
+        if let Some(profiles) = profile.and_then(|x| self.table(x, "profile")) {
+            for profile in profiles {
+                self.profile(profile);
+            }
+        }
+        self.selection(papers, rules, subjects, merged_on_load);
+        if let Some(layout) = layout {
+            self.layout(layout);
+        }
+        self.schedule(bandwidth, windows);
+    }
+
+    /// Checks the download schedule, shared by the configuration and its profiles.
+    fn schedule(&mut self, bandwidth: Option<&Node>, windows: Option<&Node>) {
+        if let Some(bandwidth) = bandwidth {
+            if let Some(Err(e)) = self.string(bandwidth, "bandwidth").map(Rate::from_str) {
+                self.diagnostics.push(error(bandwidth.line, bandwidth.column, e, None));
+            }
+        }
+        if let Some(windows) = windows.and_then(|x| self.array(x, "windows")) {
+            for window in windows {
+                if let Some(Err(e)) = self.string(window, "download window").map(Window::from_str) {
+                    self.diagnostics.push(error(window.line, window.column, e, None));
+                }
+            }
+        }
+    }
+
+    fn profile(&mut self, profile: &Entry) {
+        let what = format!("profile \"{}\"", profile.key);
+        let entries = match self.table(&profile.value, &what) {
+            Some(entries) => entries,
+            None => return,
+        };
+        let [papers, rules, subjects, layout, bandwidth, windows] = self.fields(
+            &profile.value,
+            entries,
+            &what,
+            &model_keys::<Configuration>()
+                .iter()
+                .copied()
+                .filter(|x| *x != "version")
+                .collect::<Vec<_>>(),
+            ["papers", "rules", "subjects", "layout", "bandwidth", "windows"],
+            &[],
+        );
+        self.selection(papers, rules, subjects, false);
+        if let Some(layout) = layout {
+            self.layout(layout);
+        }
+        self.schedule(bandwidth, windows);
+    }
+
+    fn layout(&mut self, node: &Node) {
+        if let Some(template) = self.string(node, "layout") {
+            match Layout::from_str(template) {
+                Ok(layout) => {
+                    for (what, fields) in layout.ambiguities() {
+                        self.diagnostics.push(warning(
+                            node.line,
+                            node.column,
+                            format!("Papers that only differ by {} get the same path", what),
+                            Some(format!("add one of {}", fields)),
+                        ));
+                    }
+                }
+                Err(e) => self.diagnostics.push(error(node.line, node.column, e, None)),
+            }
+        }
+    }
+
+    /// Checks the fields selecting papers, shared by the configuration and its profiles.
+    fn selection(&mut self, papers: Option<&Node>, rules: Option<&Node>, subjects: Option<&Node>, merged_on_load: bool) {
+        if let Some(papers) = papers.and_then(|x| self.array(x, "papers")) {
+            self.paper_types(papers);
+        }
+        if let Some(rules) = rules.and_then(|x| self.array(x, "rules")) {
+            for rule in rules {
+                self.rule(rule);
+            }
+        }
+        if let Some(subjects) = subjects.and_then(|x| self.array(x, "subjects")) {
+            let mut seen: Vec<(String, usize)> = vec![];
+            for subject in subjects {
+                if let Some(slug) = self.subject(subject) {
+                    match seen.iter().find(|(x, _)| *x == slug) {
+                        Some(_) if merged_on_load => {}
+                        Some((_, line)) => self.diagnostics.push(warning(
+                            subject.line,
+                            subject.column,
+                            format!("Duplicate subject \"{}\", first defined on line {}", slug, line),
+                            Some("merge the papers into one entry".to_string()),
+                        )),
+                        None => seen.push((slug, subject.line)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn paper_types(&mut self, papers: &[Node]) {
+        let mut seen: Vec<PaperType> = vec![];
+        for paper in papers {
+            if let Some(paper_type) = self.variant::<PaperType>(paper, "paper type") {
+                if seen.contains(&paper_type) {
+                    self.diagnostics.push(warning(
+                        paper.line,
+                        paper.column,
+                        format!("Duplicate paper type \"{:?}\"", paper_type),
+                        None,
+                    ));
+                }
+                seen.push(paper_type);
+            }
+        }
+    }
+
+    fn rule(&mut self, node: &Node) {
+        let entries = match self.table(node, "A rule") {
+            Some(entries) => entries,
+            None => return,
+        };
+        let [subjects, years, seasons, papers, components] = self.fields(
+            node,
+            entries,
+            "a rule",
+            model_keys::<SelectionRule>(),
+            ["subjects", "years", "seasons", "papers", "components"],
+            &["subjects"],
+        );
+        for subject in subjects.and_then(|x| self.array(x, "subjects")).unwrap_or_default() {
+            if let Some(value) = self.string(subject, "subject") {
+                if SyllabusCode::find(value).is_none() {
+                    self.unknown_subject(subject, value);
+                }
+            }
+        }
+        if let Some(years) = years {
+            if let Some(value) = self.string(years, "years") {
+                if let Err(e) = value.parse::<YearRange>() {
+                    self.diagnostics.push(error(
+                        years.line,
+                        years.column,
+                        e,
+                        Some("write a year or a range, e.g. \"2018-2023\", \"2020-\" or \"-2015\"".to_string()),
+                    ));
+                }
+            }
+        }
+        for season in seasons.and_then(|x| self.array(x, "seasons")).unwrap_or_default() {
+            self.variant::<Season>(season, "season");
+        }
+        if let Some(papers) = papers.and_then(|x| self.array(x, "papers")) {
+            self.paper_types(papers);
+        }
+        for component in components.and_then(|x| self.array(x, "components")).unwrap_or_default() {
+            if let Some(value) = self.string(component, "component") {
+                if value.is_empty() || !value.chars().all(|x| x.is_ascii_digit()) {
+                    self.diagnostics.push(error(
+                        component.line,
+                        component.column,
+                        format!("Malformed component \"{}\"", value),
+                        Some("components are paper numbers without the variant, e.g. \"1\"".to_string()),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Validates a subject entry, returning its access slug.
+    fn subject(&mut self, node: &Node) -> Option<String> {
+        let entries = self.table(node, "A subject")?;
+        let [syllabus_code, paper_types, papers, known] = self.fields(
+            node,
+            entries,
+            "a subject",
+            model_keys::<YearConfiguration>(),
+            ["syllabus_code", "paper_types", "papers", "known"],
+            &["syllabus_code", "papers"],
+        );
+        let slug = syllabus_code.and_then(|x| self.syllabus_code(x));
+        if let Some(paper_types) = paper_types.and_then(|x| self.array(x, "paper_types")) {
+            self.paper_types(paper_types);
+        }
+
+        let mut seen: Vec<((String, Season, PaperType, String), usize)> = vec![];
+        for paper in papers.and_then(|x| self.array(x, "papers")).unwrap_or_default() {
+            if let Some(key) = self.paper(paper) {
+                match seen.iter().find(|(x, _)| *x == key) {
+                    Some((_, line)) => self.diagnostics.push(warning(
+                        paper.line,
+                        paper.column,
+                        format!("Duplicate paper, first listed on line {}", line),
+                        None,
+                    )),
+                    None => seen.push((key, paper.line)),
+                }
+            }
+        }
+        for name in known.and_then(|x| self.array(x, "known")).unwrap_or_default() {
+            if let Some(value) = self.string(name, "known") {
+                if Paper::from_str(value).is_err() {
+                    self.diagnostics.push(error(
+                        name.line,
+                        name.column,
+                        format!("Malformed known paper \"{}\"", value),
+                        Some("known papers are file names, e.g. \"9709_s23_qp_12.pdf\"".to_string()),
+                    ));
+                }
+            }
+        }
+        slug
+    }
+
+    fn syllabus_code(&mut self, node: &Node) -> Option<String> {
+        let entries = self.table(node, "syllabus_code")?;
+        let [name, code, slug] = self.fields(
+            node,
+            entries,
+            "syllabus_code",
+            model_keys::<SyllabusCode>(),
+            ["name", "syllabus_code", "access_slug"],
+            &["name", "syllabus_code", "access_slug"],
+        );
+        let name = name.and_then(|x| self.string(x, "name"));
+        let slug = slug.and_then(|x| self.string(x, "access_slug").map(|y| (x, y)));
+        let code = code.and_then(|x| self.string(x, "syllabus_code").map(|y| (x, y)));
+        if let Some((node, code)) = code {
+            let known = SYLLABUS_CODES.iter().filter(|x| x.syllabus_code == code).collect::<Vec<_>>();
+            if known.is_empty() {
+                match name.and_then(SyllabusCode::find) {
+                    // The name usually survives a mistyped code.
+                    Some(by_name) => self.diagnostics.push(error(
+                        node.line,
+                        node.column,
+                        format!("Unknown syllabus code \"{}\"", code),
+                        Some(format!("{} is {}", by_name.name, by_name.syllabus_code)),
+                    )),
+                    None => self.unknown_subject(node, code),
+                }
+            } else if let Some((slug_node, slug)) = slug {
+                if !known.iter().any(|x| x.access_slug == slug) {
+                    self.diagnostics.push(warning(
+                        slug_node.line,
+                        slug_node.column,
+                        format!("Access slug \"{}\" doesn't belong to syllabus {}", slug, code),
+                        Some(format!("expected \"{}\"", known[0].access_slug)),
+                    ));
+                }
+            }
+        }
+        slug.map(|(_, x)| x.to_string())
+    }
+
+    fn paper(&mut self, node: &Node) -> Option<(String, Season, PaperType, String)> {
+        let entries = self.table(node, "A paper")?;
+        let [year, season, paper_type, variant] = self.fields(
+            node,
+            entries,
+            "a paper",
+            model_keys::<Paper>(),
+            ["year", "season", "paper_type", "variant"],
+            &["year", "season", "paper_type", "variant"],
+        );
+        let year = year.and_then(|x| self.year(x));
+        let season = season.and_then(|x| self.variant::<Season>(x, "season"));
+        let paper_type = paper_type.and_then(|x| self.variant::<PaperType>(x, "paper type"));
+        let variant = variant.and_then(|x| {
+            let value = self.string(x, "variant")?;
+            if !value.chars().all(|y| y.is_ascii_digit()) {
+                self.diagnostics.push(error(
+                    x.line,
+                    x.column,
+                    format!("Malformed variant \"{}\"", value),
+                    Some("variants are paper numbers, e.g. \"12\"".to_string()),
+                ));
+                return None;
+            }
+            Some(value.to_string())
+        });
+        Some((year?, season?, paper_type?, variant?))
+    }
+}
+
+fn validate_layer(content: &str, format: ConfigFormat) -> Validator {
+    let mut validator = Validator::default();
+    let root = match format {
+        ConfigFormat::Toml => parse_toml(content),
+        ConfigFormat::Json | ConfigFormat::Yaml => parse_yaml(content),
+    };
+    let root = match root {
+        Ok(root) => root,
+        Err(e) => {
+            validator.diagnostics.push(e);
+            return validator;
+        }
+    };
+    validator.root(&root);
+
+    // Anything the checks above missed still surfaces, without a precise position.
+    if !has_errors(&validator.diagnostics) {
+        if let Err(e) = Configuration::parse(content, format) {
+            validator.diagnostics.push(error(root.line, root.column, e.to_string(), None));
+        }
+    }
+    validator.diagnostics.sort_by_key(|x| (x.line, x.column));
+    validator
+}
+
+/// Checks a configuration for problems, every one positioned at the value that causes it.
+/// Included files are not followed, see `validate_file`.
+pub fn validate(content: &str, format: ConfigFormat) -> Vec<Diagnostic> {
+    validate_layer(content, format).diagnostics
+}
+
+fn validate_included(
+    path: &Path,
+    format: ConfigFormat,
+    chain: &mut Vec<PathBuf>,
+    results: &mut Vec<(PathBuf, Vec<Diagnostic>)>,
+) -> Result<(), std::io::Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut validator = validate_layer(&content, format);
+    chain.push(path.canonicalize()?);
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut included = vec![];
+    for (include, line, column) in std::mem::take(&mut validator.includes) {
+        let include = directory.join(include);
+        match include.canonicalize() {
+            Err(e) => validator.diagnostics.push(error(
+                line,
+                column,
+                format!("Included file {:?} can't be opened: {}", include, e),
+                None,
+            )),
+            Ok(canonical) if chain.contains(&canonical) => validator.diagnostics.push(error(
+                line,
+                column,
+                format!("Including {:?} creates a cycle", include),
+                None,
+            )),
+            Ok(_) => included.push(include),
+        }
+    }
+    results.push((path.to_path_buf(), validator.diagnostics));
+    for include in included {
+        validate_included(&include, ConfigFormat::from_path(&include), chain, results)?;
+    }
+    chain.pop();
+    Ok(())
+}
+
+/// Validates a configuration file and every file it includes, diagnostics are grouped by file.
+pub fn validate_file(
+    path: &Path,
+    format: Option<ConfigFormat>,
+) -> Result<Vec<(PathBuf, Vec<Diagnostic>)>, std::io::Error> {
+    let mut results = vec![];
+    validate_included(path, ConfigFormat::resolve(format, path), &mut vec![], &mut results)?;
+    Ok(results)
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|x| x.severity == Severity::Error)
+}
+
+/// Logs every diagnostic prefixed with the file it belongs to, returning the number of errors.
+pub fn report(results: &[(PathBuf, Vec<Diagnostic>)]) -> usize {
+    let mut errors = 0;
+    for (path, diagnostics) in results {
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Severity::Error => {
+                    error!("{}:{}", path.display(), diagnostic);
+                    errors += 1;
+                }
+                Severity::Warning => warn!("{}:{}", path.display(), diagnostic),
+            }
+        }
+    }
+    errors
+}
+
+pub fn handle_validate(config: ValidateConfiguration) {
+    let results = match validate_file(&config.config, config.format) {
+        Ok(results) => results,
+        Err(e) => {
+            error!("Failed to read configuration file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let errors = report(&results);
+    let warnings = results.iter().map(|(_, x)| x.len()).sum::<usize>() - errors;
+    if errors > 0 {
+        error!("{} errors, {} warnings.", errors, warnings);
+        std::process::exit(1);
+    }
+    info!("Configuration is valid, {} warnings.", warnings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(content: &str) -> Vec<String> {
+        validate(content, ConfigFormat::Toml)
+            .into_iter()
+            .filter(|x| x.severity == Severity::Error)
+            .map(|x| x.message)
+            .collect()
+    }
+
+    #[test]
+    fn profiles_may_set_the_schedule() {
+        let content = r#"
+version = 2
+papers = ["QP"]
+subjects = []
+bandwidth = "1M"
+
+[profile.night]
+bandwidth = "4MB"
+windows = ["22:00-06:00"]
+"#;
+        assert_eq!(messages(content), Vec::<String>::new());
+    }
+
+    #[test]
+    fn profile_schedules_are_checked() {
+        let content = r#"
+version = 2
+papers = ["QP"]
+subjects = []
+
+[profile.night]
+bandwidth = "fast"
+windows = ["22:00-22:00"]
+"#;
+        assert_eq!(messages(content).len(), 2, "{:?}", messages(content));
+    }
+
+    #[test]
+    fn allowed_keys_follow_the_model() {
+        assert!(model_keys::<Configuration>().contains(&"windows"));
+        assert!(model_keys::<YearConfiguration>().contains(&"known"));
+        assert_eq!(model_keys::<Paper>(), ["year", "season", "paper_type", "variant"]);
+        let content = r#"
+version = 2
+papers = ["QP"]
+subjects = []
+windos = ["22:00-06:00"]
+
+[profile.night]
+version = 2
+"#;
+        assert_eq!(
+            messages(content),
+            ["Unknown key \"windos\" in the configuration", "Unknown key \"version\" in profile \"night\""]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::{io::IsTerminal, path::PathBuf};
+
+use futures::StreamExt;
+use inquire::{Confirm, InquireError, MultiSelect, Select};
+
+use crate::{
+    config_format::ConfigFormat,
+    config_gen::{generate, runtime, write_configuration, PaperGenerationConfig},
+    configuration::{PaperType, Season, SyllabusCode, BOARDS, SYLLABUS_CODES},
+    scraper::get_all_years,
+};
+
+#[derive(Debug)]
+pub struct WizardConfiguration {
+    pub output: PathBuf,
+    pub format: Option<ConfigFormat>,
+    /// Preselected paper types.
+    pub papers: Vec<PaperType>,
+    /// Preselected seasons.
+    pub seasons: Vec<Season>,
+    pub threads: u8,
+}
+
+/// An option of a prompt, shown by its label.
+struct Choice<T> {
+    label: String,
+    value: T,
+}
+
+impl<T> std::fmt::Display for Choice<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// The answer to a prompt. Backing out with Esc or Ctrl-C ends the wizard without writing anything.
+fn answer<T>(result: Result<T, InquireError>) -> T {
+    match result {
+        Ok(x) => x,
+        Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => {
+            info!("Wizard cancelled, nothing was written.");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            error!("Failed to read the answer: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Asks for a non-empty selection until one is given.
+fn pick_many<T>(prompt: &str, choices: Vec<Choice<T>>, selected: &[usize]) -> Vec<T> {
+    loop {
+        let picked = answer(
+            MultiSelect::new(prompt, choices.iter().collect())
+                .with_default(selected)
+                .with_page_size(12)
+                .raw_prompt(),
+        );
+        if !picked.is_empty() {
+            let indices = picked.iter().map(|x| x.index).collect::<Vec<_>>();
+            return choices
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| indices.contains(i))
+                .map(|(_, x)| x.value)
+                .collect();
+        }
+        eprintln!("Pick at least one, space selects and enter confirms.");
+    }
+}
+
+/// Every year any of the subjects has papers for, oldest first.
+fn available_years(syllabus_codes: &[SyllabusCode], threads: u8) -> Vec<String> {
+    let listed = runtime(threads).block_on(
+        futures::stream::iter(syllabus_codes)
+            .map(|x| async move {
+                match get_all_years(x).await {
+                    Ok(years) => years,
+                    Err(e) => {
+                        error!("Failed to fetch years for {}: {:?}", x.name, e);
+                        vec![]
+                    }
+                }
+            })
+            .buffer_unordered(threads.max(1) as usize)
+            .collect::<Vec<_>>(),
+    );
+    let mut years = listed.into_iter().flatten().collect::<Vec<_>>();
+    years.sort();
+    years.dedup();
+    years
+}
+
+/// A syllabus as shown to the user, with its access slug when its code is shared.
+fn subject_label(syllabus_code: &SyllabusCode) -> String {
+    match syllabus_code.is_ambiguous() {
+        true => format!("{} ({}, {})", syllabus_code.name, syllabus_code.syllabus_code, syllabus_code.access_slug),
+        false => format!("{} ({})", syllabus_code.name, syllabus_code.syllabus_code),
+    }
+}
+
+/// How `--subjects` names a syllabus, by access slug when its code is shared.
+fn subject_flag(syllabus_code: &SyllabusCode) -> String {
+    match syllabus_code.is_ambiguous() {
+        true => format!("{:?}", syllabus_code.access_slug),
+        false => syllabus_code.syllabus_code.clone(),
+    }
+}
+
+/// The `generate-config` flags selecting the same papers, so a run can be repeated without the wizard.
+fn command_line(config: &WizardConfiguration, selection: &PaperGenerationConfig) -> String {
+    let join = |x: Vec<String>| x.join(",");
+    format!(
+        "gce-scraper generate-config -o {:?} -s {} -y {} -p {} --seasons {}",
+        config.output,
+        join(selection.subjects.clone().unwrap_or_default()),
+        join(selection.years.clone().unwrap_or_default()),
+        join(selection.papers.iter().map(|x| x.to_string()).collect()),
+        join(
+            selection
+                .seasons
+                .iter()
+                .flatten()
+                .map(|x| format!("{:?}", x).to_lowercase())
+                .collect()
+        ),
+    )
+}
+
+pub fn handle_wizard(config: WizardConfiguration) {
+    if !std::io::stdin().is_terminal() {
+        error!("The wizard needs a terminal, pass --subjects, --years, --papers and --seasons instead.");
+        std::process::exit(1);
+    }
+
+    let board = answer(Select::new("Board:", BOARDS.to_vec()).prompt());
+    debug!("Selected board {}.", board);
+
+    let subjects = pick_many(
+        "Subjects (type to search by name or code):",
+        SYLLABUS_CODES
+            .iter()
+            .filter(|x| x.board() == board)
+            .map(|x| Choice {
+                label: subject_label(x),
+                value: x.clone(),
+            })
+            .collect(),
+        &[],
+    );
+
+    let years = available_years(&subjects, config.threads);
+    if years.is_empty() {
+        error!("No years could be fetched for the selected subjects.");
+        std::process::exit(1);
+    }
+    let first = answer(Select::new("From year:", years.clone()).prompt());
+    let later = years.iter().filter(|x| **x >= first).cloned().collect::<Vec<_>>();
+    let last = answer(
+        Select::new("To year:", later.clone())
+            .with_starting_cursor(later.len() - 1)
+            .prompt(),
+    );
+    let years = later.into_iter().filter(|x| *x <= last).collect::<Vec<_>>();
+
+    let all_seasons = [Season::March, Season::Summer, Season::Winter];
+    let seasons = pick_many(
+        "Seasons:",
+        all_seasons
+            .iter()
+            .map(|x| Choice {
+                label: format!("{} ({:?})", x.session_name(), x),
+                value: x.clone(),
+            })
+            .collect(),
+        &(0..all_seasons.len())
+            .filter(|i| config.seasons.contains(&all_seasons[*i]))
+            .collect::<Vec<_>>(),
+    );
+
+    let all_papers = [
+        PaperType::QP,
+        PaperType::MS,
+        PaperType::ER,
+        PaperType::IN,
+        PaperType::GT,
+        PaperType::IR,
+        PaperType::CI,
+    ];
+    let papers = pick_many(
+        "Paper types:",
+        all_papers
+            .iter()
+            .map(|x| Choice {
+                label: format!("{} ({})", x.long_name(), x),
+                value: x.clone(),
+            })
+            .collect(),
+        &(0..all_papers.len())
+            .filter(|i| config.papers.contains(&all_papers[*i]))
+            .collect::<Vec<_>>(),
+    );
+
+    // The flag based path does the rest with the picked syllabi, both give the same configuration.
+    let selection = PaperGenerationConfig {
+        papers,
+        years: Some(years),
+        subjects: Some(subjects.iter().map(subject_flag).collect()),
+        seasons: Some(seasons),
+    };
+    let command = command_line(&config, &selection);
+    info!("Fetching the paper listings...");
+    let generated = generate(subjects, selection, config.threads);
+
+    let mut total = 0;
+    for subject in &generated.subjects {
+        println!("{}: {} papers", subject_label(&subject.syllabus_code), subject.papers.len());
+        total += subject.papers.len();
+    }
+    println!("Total: {} papers", total);
+    if total == 0 {
+        error!("No papers found, nothing was written.");
+        std::process::exit(1);
+    }
+
+    let question = match config.output.exists() {
+        true => format!("Replace {:?} with these {} papers?", config.output, total),
+        false => format!("Write these {} papers to {:?}?", total, config.output),
+    };
+    if !answer(Confirm::new(&question).with_default(!config.output.exists()).prompt()) {
+        info!("Nothing was written.");
+        return;
+    }
+    let format = ConfigFormat::resolve(config.format, &config.output);
+    write_configuration(&config.output, format, &generated);
+    println!("The same configuration can be generated again with:\n  {}", command);
+}
@@ -1,43 +1,227 @@
-use std::{fmt::Display, fs::File, io::Read, str::FromStr, sync::LazyLock};
+use std::{fmt::Display, fs::File, io::Read, path::Path, str::FromStr, sync::LazyLock};
 
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    config_compose::{apply_profile, load_layers},
+    config_format::ConfigFormat,
+    config_migrate::migrate,
+    layout::Layout,
+    throttle::{Rate, Window},
+};
+
+/// Schema version written by this build. Older versions are upgraded when loaded, see `config_migrate`.
+pub const CONFIG_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Configuration {
+    #[serde(default)]
+    pub version: u32,
+    /// Paper types to download, every type when empty.
+    #[serde(default)]
     pub papers: Vec<PaperType>,
+    /// Selection rules, resolved against the live listing when downloading.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<SelectionRule>,
+    #[serde(default)]
     pub subjects: Vec<YearConfiguration>,
+    /// Where papers go inside the download folder, the default layout when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    /// Download speed shared by all transfers, e.g. "2MB" per second. Unlimited when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<Rate>,
+    /// Times of day papers may be downloaded, e.g. "18:00-07:00". Any time when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<Window>,
+}
+
+/// Selects papers by subject, year range, season, type and component instead of listing each paper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionRule {
+    pub subjects: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub years: Option<YearRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seasons: Option<Vec<Season>>,
+    /// Falls back to the top level `papers` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub papers: Option<Vec<PaperType>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub components: Option<Vec<String>>,
+}
+
+/// Inclusive year range written as "2018-2023", "2018-", "-2020" or "2021".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct YearRange {
+    pub from: Option<u32>,
+    pub to: Option<u32>,
+}
+
+impl YearRange {
+    pub fn contains(&self, year: &str) -> bool {
+        let year = match year.parse::<u32>() {
+            Ok(year) => year,
+            Err(_) => return false,
+        };
+        self.from.is_none_or(|x| year >= x) && self.to.is_none_or(|x| year <= x)
+    }
+}
+
+impl FromStr for YearRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |x: &str| -> Result<Option<u32>, String> {
+            let x = x.trim();
+            if x.is_empty() {
+                return Ok(None);
+            }
+            match x.parse::<u32>() {
+                Ok(year) if x.len() == 4 => Ok(Some(year)),
+                _ => Err(format!("Invalid year \"{}\" in range \"{}\"", x, value)),
+            }
+        };
+        let range = match value.split_once('-') {
+            Some((from, to)) => YearRange {
+                from: parse(from)?,
+                to: parse(to)?,
+            },
+            None => {
+                let year = parse(value)?;
+                YearRange { from: year, to: year }
+            }
+        };
+        Ok(range)
+    }
+}
+
+impl TryFrom<String> for YearRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        YearRange::from_str(&value)
+    }
+}
+
+impl From<YearRange> for String {
+    fn from(value: YearRange) -> Self {
+        match (value.from, value.to) {
+            (Some(from), Some(to)) if from == to => from.to_string(),
+            (from, to) => format!(
+                "{}-{}",
+                from.map(|x| x.to_string()).unwrap_or_default(),
+                to.map(|x| x.to_string()).unwrap_or_default()
+            ),
+        }
+    }
 }
 
 impl TryFrom<File> for Configuration {
     type Error = std::io::Error;
     // parses a configuration file into a Configuration struct, TOML format
     fn try_from(value: File) -> Result<Self, Self::Error> {
-        let mut buff = std::io::BufReader::new(value);
-        // let buff_lines= std::io::BufRead::lines(buff);
-        let mut conf_str: Vec<u8> = Vec::new();
-        buff.read_to_end(&mut conf_str).unwrap();
-        let config = toml::from_str(std::str::from_utf8(&conf_str).unwrap());
-        let config = match config {
-            Ok(config) => config,
-            Err(_) => {
+        Configuration::read(value, ConfigFormat::Toml)
+    }
+}
+
+impl Configuration {
+    /// Loads a configuration file, detecting the format from the extension unless one is given.
+    pub fn load(path: &Path, format: Option<ConfigFormat>) -> Result<Self, std::io::Error> {
+        Configuration::load_profile(path, format, None)
+    }
+
+    /// Loads a configuration with its includes merged and `profile` applied on top.
+    pub fn load_profile(path: &Path, format: Option<ConfigFormat>, profile: Option<&str>) -> Result<Self, std::io::Error> {
+        let config = apply_profile(load_layers(path, format)?, profile)?;
+        toml::Value::Table(config).try_into().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse configuration file: {}", e),
+            )
+        })
+    }
+
+    pub fn read(file: File, format: ConfigFormat) -> Result<Self, std::io::Error> {
+        let mut buff = std::io::BufReader::new(file);
+        let mut conf_str = String::new();
+        buff.read_to_string(&mut conf_str)?;
+        Configuration::parse(&conf_str, format)
+    }
+
+    /// Every paper type the configuration or one of its subject overrides selects, empty when any of them
+    /// selects every type.
+    pub fn selected_paper_types(&self) -> Vec<PaperType> {
+        if self.papers.is_empty() {
+            return vec![];
+        }
+        let mut papers = self.papers.clone();
+        for paper_types in self.subjects.iter().filter_map(|x| x.paper_types.as_ref()) {
+            if paper_types.is_empty() {
+                return vec![];
+            }
+            for paper_type in paper_types {
+                if !papers.contains(paper_type) {
+                    papers.push(paper_type.clone());
+                }
+            }
+        }
+        papers
+    }
+
+    /// Drops every paper whose type isn't selected, by the subject's own `paper_types` or the global `papers`.
+    /// An empty selection keeps every type. Returns the number of papers dropped.
+    pub fn filter_paper_types(&mut self) -> usize {
+        let mut dropped = 0;
+        for subject in self.subjects.iter_mut() {
+            let selected = subject.paper_types.as_ref().unwrap_or(&self.papers);
+            if selected.is_empty() {
+                continue;
+            }
+            let before = subject.papers.len();
+            subject.papers.retain(|x| selected.contains(&x.paper_type));
+            dropped += before - subject.papers.len();
+        }
+        dropped
+    }
+
+    /// Parses and migrates a configuration held in memory.
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Self, std::io::Error> {
+        let config = match format.parse(content) {
+            Ok(config) => migrate(config)?,
+            Err(e) => {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
-                    "Failed to parse configuration file.",
+                    format!("Failed to parse configuration file: {}", e),
                 ))
             }
         };
-        Ok(config)
+        match toml::Value::Table(config).try_into() {
+            Ok(config) => Ok(config),
+            Err(e) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse configuration file: {}", e),
+            )),
+        }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct YearConfiguration {
     pub syllabus_code: SyllabusCode,
+    /// Paper types downloaded for this subject, replacing `Configuration.papers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper_types: Option<Vec<PaperType>>,
     pub papers: Vec<Paper>,
+    /// File names of every paper `generate-config` has seen listed. A known paper missing from `papers`
+    /// was removed by hand, `--update` doesn't add it back. Unset in configurations from before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Paper {
     pub year: String,
     pub season: Season,
@@ -119,13 +303,38 @@ impl Paper {
         }
     }
 
+    /// Human readable title, e.g. "Mathematics 9709 – May/June 2023 – Paper 12".
+    pub fn title(&self, syllabus_code: &SyllabusCode) -> String {
+        let mut title = format!(
+            "{} {} – {} {}",
+            syllabus_code.name,
+            syllabus_code.syllabus_code,
+            self.season.session_name(),
+            self.year
+        );
+        if !self.variant.is_empty() {
+            title.push_str(&format!(" – Paper {}", self.variant));
+        }
+        title
+    }
+
+    /// The component (paper number) part of the variant, e.g. "1" for variant "12".
+    pub fn component(&self) -> &str {
+        self.variant.get(..1).unwrap_or("")
+    }
+
+    /// The administrative variant part of the variant, e.g. "2" for variant "12".
+    pub fn variant_number(&self) -> &str {
+        self.variant.get(1..).unwrap_or("")
+    }
+
     pub fn get_ref_filename(&self, syllabus_code: &SyllabusCode) -> String {
-        // if examiners report, SYLLABUSCODE_SEASONCHAR.YEAR(LAST_TWO_CODE)_ER.pdf
+        // if examiners report or grade thresholds, SYLLABUSCODE_SEASONCHAR.YEAR(LAST_TWO_CODE)_ER.pdf
         // else, SYLLABUSCODE_SEASONCHAR.PAPERTYPE.VARIANT.pdf
         let year_format = &self.year[self.year.len() - 2..];
         match self.paper_type {
-            PaperType::ER => {
-                format!("{}_{}{}_er.pdf", syllabus_code.syllabus_code, self.season, year_format)
+            PaperType::ER | PaperType::GT => {
+                format!("{}_{}{}_{}.pdf", syllabus_code.syllabus_code, self.season, year_format, self.paper_type)
             }
             _ => {
                 format!(
@@ -148,6 +357,26 @@ pub enum Season {
     March,  // Refered to as "m"
 }
 
+impl Season {
+    /// Human readable exam session name, e.g. "May/June".
+    pub fn session_name(&self) -> &'static str {
+        match self {
+            Season::Winter => "October/November",
+            Season::Summer => "May/June",
+            Season::March => "February/March",
+        }
+    }
+    /// Position of the session within its year, March before Summer before Winter. The variants aren't declared
+    /// in that order, so sorting by `Season` itself doesn't follow the calendar.
+    pub fn calendar_order(&self) -> u8 {
+        match self {
+            Season::March => 0,
+            Season::Summer => 1,
+            Season::Winter => 2,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum SeasonParseError {
     InvalidSeasonCharacter,
@@ -165,6 +394,21 @@ pub enum PaperType {
     CI,
 }
 
+impl PaperType {
+    /// Human readable document name, e.g. "Mark Scheme".
+    pub fn long_name(&self) -> &'static str {
+        match self {
+            PaperType::QP => "Question Paper",
+            PaperType::MS => "Mark Scheme",
+            PaperType::ER => "Examiner Report",
+            PaperType::IN => "Insert",
+            PaperType::GT => "Grade Thresholds",
+            PaperType::IR => "Resource Insert",
+            PaperType::CI => "Confidential Instructions",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum PaperTypeParseError {
     InvalidPaperTypeCharacter,
@@ -269,8 +513,59 @@ impl SyllabusCode {
             access_slug: access_slug.to_string(),
         }
     }
+
+    /// Finds the syllabus a user supplied subject name or syllabus code prefix refers to. An exact access slug
+    /// picks one of the syllabi sharing a code.
+    pub fn find(query: &str) -> Option<SyllabusCode> {
+        SYLLABUS_CODES
+            .iter()
+            .find(|code| code.access_slug == query)
+            .or_else(|| SYLLABUS_CODES.iter().find(|code| code.matches(query)))
+            .cloned()
+    }
+
+    /// The board publishing this syllabus.
+    pub fn board(&self) -> Board {
+        A_LEVELS
+    }
+
+    /// Whether another syllabus has the same code, so only the access slug tells them apart.
+    pub fn is_ambiguous(&self) -> bool {
+        SYLLABUS_CODES
+            .iter()
+            .any(|x| x.syllabus_code == self.syllabus_code && x.access_slug != self.access_slug)
+    }
+
+    /// Whether a user supplied subject name or syllabus code prefix refers to this syllabus.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.name.to_lowercase().starts_with(&query)
+            || self.syllabus_code.to_lowercase().starts_with(&query)
+    }
 }
 
+/// An exam board whose papers can be downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    pub name: &'static str,
+    /// Where the papers of its syllabi are published.
+    pub base_url: &'static str,
+}
+
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub const A_LEVELS: Board = Board {
+    name: "Cambridge International A Levels",
+    base_url: "https://papers.gceguide.cc/a-levels/",
+};
+
+/// Every board, `SYLLABUS_CODES` are all on the first.
+pub const BOARDS: [Board; 1] = [A_LEVELS];
+
 pub static SYLLABUS_CODES: LazyLock<Vec<SyllabusCode>> = LazyLock::new(|| {
     vec![
         SyllabusCode::new("Accounting", "accounting-(9706)", "9706"),
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use indicatif::HumanBytes;
+
+use crate::{
+    library::METADATA_DIR,
+    manifest::{fingerprint, library_path, Manifest},
+};
+
+/// Default store inside the library, pass a shared folder to deduplicate across libraries.
+const STORE_DIR: &str = "store";
+
+#[derive(Debug)]
+pub struct DedupConfiguration {
+    pub input_folder: PathBuf,
+    /// Content-addressed store, `.gce-scraper/store` of the library when missing.
+    pub store: Option<PathBuf>,
+    /// Only report what deduplication would save.
+    pub dry_run: bool,
+}
+
+/// How a library file ended up pointing at the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Hard,
+    Symbolic,
+}
+
+/// Where the store keeps a document, `<store>/3f/<sha256>.pdf`.
+pub fn object_path(store: &Path, sha256: &str) -> PathBuf {
+    store.join(&sha256[..2]).join(format!("{}.pdf", sha256))
+}
+
+/// Whether `path` already is the store object, through a hardlink or a symlink.
+fn is_linked(path: &Path, object: &Path) -> bool {
+    if let Ok(target) = std::fs::read_link(path) {
+        return target.canonicalize().ok() == object.canonicalize().ok();
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let (Ok(a), Ok(b)) = (std::fs::metadata(path), std::fs::metadata(object)) {
+            return a.dev() == b.dev() && a.ino() == b.ino();
+        }
+    }
+    false
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> Result<(), std::io::Error> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Replaces `path` with a link to `object`, a hardlink when the file system allows one.
+fn link_to(object: &Path, path: &Path) -> Result<Link, std::io::Error> {
+    // The first copy of a document is stored by hardlinking it, it needs no replacing.
+    if is_linked(path, object) {
+        return Ok(Link::Hard);
+    }
+    let temporary = path.with_extension("dedup");
+    let _ = std::fs::remove_file(&temporary);
+    let link = match std::fs::hard_link(object, &temporary) {
+        Ok(_) => Link::Hard,
+        Err(_) => {
+            // Symlinks need an absolute target to work from anywhere.
+            symlink(&object.canonicalize()?, &temporary)?;
+            Link::Symbolic
+        }
+    };
+    std::fs::rename(&temporary, path)?;
+    Ok(link)
+}
+
+/// Puts a file into the store under its checksum, unless the content is stored already.
+fn store(path: &Path, object: &Path) -> Result<(), std::io::Error> {
+    if object.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = object.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = object.with_extension("tmp");
+    // A symlink would be linked itself, not its content.
+    if path.is_symlink() || std::fs::hard_link(path, &temporary).is_err() {
+        // Another file system, the store gets its own copy.
+        std::fs::copy(path, &temporary)?;
+    }
+    std::fs::rename(&temporary, object)
+}
+
+pub fn handle_dedup(config: DedupConfiguration) {
+    let manifest = match Manifest::load(&config.input_folder) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!("Failed to read the library manifest: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if manifest.files.is_empty() {
+        error!(
+            "{:?} has no record of downloaded papers, only papers downloaded by the scraper are deduplicated.",
+            config.input_folder
+        );
+        std::process::exit(1);
+    }
+    let store_folder = config
+        .store
+        .clone()
+        .unwrap_or(config.input_folder.join(METADATA_DIR).join(STORE_DIR));
+
+    let mut documents: HashMap<String, u64> = HashMap::new();
+    let (mut files, mut total, mut linked, mut symlinks, mut failed) = (0, 0, 0, 0, 0);
+    let mut saved = 0;
+    for entry in &manifest.files {
+        let path = library_path(&config.input_folder, &entry.path);
+        let (size, sha256) = match fingerprint(&path) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                warn!("Skipping {}: {}", entry.path, e);
+                continue;
+            }
+        };
+        // Damaged files must not end up shared by every copy.
+        if !entry.sha256.is_empty() && entry.sha256 != sha256 {
+            warn!("Skipping {}, it changed since it was downloaded, run verify first.", entry.path);
+            continue;
+        }
+        files += 1;
+        total += size;
+        let duplicate = documents.insert(sha256.clone(), size).is_some();
+
+        let object = object_path(&store_folder, &sha256);
+        if is_linked(&path, &object) {
+            continue;
+        }
+        // The first copy of a document becomes the stored one, every later copy is space freed.
+        let frees = match duplicate || object.exists() {
+            true => size,
+            false => 0,
+        };
+        if config.dry_run {
+            saved += frees;
+            continue;
+        }
+        match store(&path, &object).and_then(|_| link_to(&object, &path)) {
+            Ok(link) => {
+                debug!("Linked {} to {:?}", entry.path, object);
+                saved += frees;
+                linked += 1;
+                symlinks += (link == Link::Symbolic) as usize;
+            }
+            Err(e) => {
+                error!("Failed to deduplicate {}: {}", entry.path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    let stored = documents.values().sum::<u64>();
+    println!("Store: {:?}", store_folder);
+    println!("{} papers, {} unique documents", files, documents.len());
+    println!(
+        "{} of papers take {} once deduplicated, {} saved",
+        HumanBytes(total),
+        HumanBytes(stored),
+        HumanBytes(total - stored)
+    );
+    match config.dry_run {
+        true => println!("This run would free {}, nothing was changed.", HumanBytes(saved)),
+        false => println!("Linked {} papers this run, freeing {}.", linked, HumanBytes(saved)),
+    }
+    if symlinks > 0 {
+        warn!(
+            "{} papers are symlinks because the store is on another file system, keep the store with the library.",
+            symlinks
+        );
+    }
+    if failed > 0 {
+        error!("{} papers could not be deduplicated.", failed);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+
+use clap::{ArgAction, Command};
+use toml::{Table, Value};
+
+/// Prefix of the environment variables supplying option defaults.
+pub const ENV_PREFIX: &str = "GCE_SCRAPER";
+
+/// Options that only make sense typed out, they never take a default.
+const SKIPPED: [&str; 4] = ["help", "version", "verbose", "quiet"];
+
+/// Where the effective default of an option comes from, highest precedence first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefaultSource {
+    /// Named environment variable.
+    Environment(String),
+    UserFile(PathBuf),
+    BuiltIn,
+    Unset,
+}
+
+impl std::fmt::Display for DefaultSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DefaultSource::Environment(_) => write!(f, "environment"),
+            DefaultSource::UserFile(x) => write!(f, "user defaults {}", x.display()),
+            DefaultSource::BuiltIn => write!(f, "built-in"),
+            DefaultSource::Unset => write!(f, "unset"),
+        }
+    }
+}
+
+/// The default a command line option takes when it isn't given.
+#[derive(Debug, Clone)]
+pub struct OptionDefault {
+    /// Subcommand names leading to the option, empty for top level options.
+    pub path: Vec<String>,
+    pub id: String,
+    pub long: String,
+    pub value: Option<String>,
+    pub source: DefaultSource,
+}
+
+impl OptionDefault {
+    /// e.g. `GCE_SCRAPER_DOWNLOAD_OUTPUT`.
+    pub fn env_var(&self) -> String {
+        std::iter::once(ENV_PREFIX.to_string())
+            .chain(self.path.iter().cloned())
+            .chain(std::iter::once(self.long.clone()))
+            .collect::<Vec<_>>()
+            .join("_")
+            .replace('-', "_")
+            .to_uppercase()
+    }
+}
+
+/// The parsed user defaults file.
+#[derive(Debug, Default)]
+pub struct UserDefaults {
+    pub path: Option<PathBuf>,
+    pub table: Table,
+}
+
+/// `$XDG_CONFIG_HOME/gce-scraper/defaults.toml` or the platform equivalent.
+pub fn defaults_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|x| x.join("gce-scraper").join("defaults.toml"))
+}
+
+/// Reads the user defaults file, a missing file means no defaults.
+pub fn load_user_defaults() -> Result<UserDefaults, String> {
+    let path = match defaults_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(UserDefaults::default()),
+    };
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+    let table = toml::from_str(&content).map_err(|e| format!("{:?}: {}", path, e))?;
+    Ok(UserDefaults {
+        path: Some(path),
+        table,
+    })
+}
+
+fn file_value(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.clone(),
+        Value::Array(x) => x.iter().map(file_value).collect::<Vec<_>>().join(","),
+        x => x.to_string(),
+    }
+}
+
+fn is_flag(action: &ArgAction) -> bool {
+    matches!(action, ArgAction::SetTrue | ArgAction::SetFalse)
+}
+
+fn collect(cmd: &Command, path: &[String], table: Option<&Table>, file: &Option<PathBuf>, defaults: &mut Vec<OptionDefault>) {
+    for arg in cmd.get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) if !SKIPPED.contains(&long) => long.to_string(),
+            _ => continue,
+        };
+        let mut option = OptionDefault {
+            path: path.to_vec(),
+            id: arg.get_id().to_string(),
+            long,
+            value: None,
+            source: DefaultSource::Unset,
+        };
+        let built_in = arg
+            .get_default_values()
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let env_var = option.env_var();
+        if let Ok(value) = std::env::var(&env_var) {
+            let value = match is_flag(arg.get_action()) {
+                true => ["1", "true", "yes", "on"]
+                    .contains(&value.to_lowercase().as_str())
+                    .to_string(),
+                false => value,
+            };
+            option.value = Some(value);
+            option.source = DefaultSource::Environment(env_var);
+        } else if let (Some(value), Some(file)) = (table.and_then(|x| x.get(&option.long)), file) {
+            option.value = Some(file_value(value));
+            option.source = DefaultSource::UserFile(file.clone());
+        } else if !built_in.is_empty() {
+            option.value = Some(built_in.join(","));
+            option.source = DefaultSource::BuiltIn;
+        }
+        defaults.push(option);
+    }
+    for sub in cmd.get_subcommands() {
+        let mut path = path.to_vec();
+        path.push(sub.get_name().to_string());
+        let table = table.and_then(|x| x.get(sub.get_name())).and_then(Value::as_table);
+        collect(sub, &path, table, file, defaults);
+    }
+}
+
+/// The effective default of every option of `cmd` and its subcommands.
+pub fn collect_defaults(cmd: &Command, user: &UserDefaults) -> Vec<OptionDefault> {
+    let mut defaults = vec![];
+    collect(cmd, &[], Some(&user.table), &user.path, &mut defaults);
+    defaults
+}
+
+/// Keys of the user defaults file that don't name an option or subcommand.
+pub fn unknown_keys(cmd: &Command, table: &Table) -> Vec<String> {
+    let mut unknown = vec![];
+    for (key, value) in table {
+        let is_option = cmd
+            .get_arguments()
+            .any(|x| x.get_long() == Some(key.as_str()) && !SKIPPED.contains(&key.as_str()));
+        match (cmd.find_subcommand(key), value) {
+            (Some(sub), Value::Table(table)) => unknown.extend(
+                unknown_keys(sub, table)
+                    .into_iter()
+                    .map(|x| format!("{}.{}", key, x)),
+            ),
+            _ if is_option => {}
+            _ => unknown.push(key.clone()),
+        }
+    }
+    unknown
+}
+
+fn apply(cmd: Command, path: &[String], option: &OptionDefault, value: String) -> Command {
+    match path.split_first() {
+        None => cmd.mut_arg(option.id.as_str(), |x| x.default_value(value)),
+        Some((sub, rest)) => cmd.mut_subcommand(sub.as_str(), |x| apply(x, rest, option, value)),
+    }
+}
+
+/// Turns environment and user file values into option defaults, so anything typed on the command line still wins.
+pub fn apply_defaults(mut cmd: Command, defaults: &[OptionDefault]) -> Command {
+    for option in defaults {
+        if let (Some(value), DefaultSource::Environment(_) | DefaultSource::UserFile(_)) = (&option.value, &option.source) {
+            cmd = apply(cmd, &option.path, option, value.clone());
+        }
+    }
+    cmd
+}
+
+#[derive(Debug)]
+pub struct DefaultsConfiguration {
+    pub defaults: Vec<OptionDefault>,
+}
+
+pub fn handle_defaults(config: DefaultsConfiguration) {
+    match defaults_path() {
+        Some(path) => info!("User defaults file: {:?}", path),
+        None => info!("No configuration directory, user defaults are unavailable."),
+    }
+    for option in config.defaults {
+        let command = match option.path.is_empty() {
+            true => "gce-scraper".to_string(),
+            false => option.path.join(" "),
+        };
+        println!(
+            "{} --{} = {} ({}, {})",
+            command,
+            option.long,
+            option.value.as_deref().unwrap_or("-"),
+            option.source,
+            option.env_var()
+        );
+    }
+}
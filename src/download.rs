@@ -1,150 +1,616 @@
-use std::{fs::File, path::PathBuf};
+use std::{collections::{HashMap, HashSet}, io::{IsTerminal, Write}, path::{Path, PathBuf}, sync::Arc};
 
 use futures::{stream, StreamExt};
-use par_stream::ParStreamExt;
 
-use crate::{configuration::{Configuration, Paper}, scraper::save_paper};
+use crate::{archive::{destination, entries, ArchiveOptions, Archives}, config_format::ConfigFormat, config_gen::runtime, config_validate::{report, validate_file}, configuration::{Configuration, Paper, SyllabusCode}, layout::Layout, manifest::{fingerprint, library_path, move_to_trash, relative_path, trash_folder, Manifest, ManifestEntry}, metadata::apply_metadata, rules::{resolve_rules, IncompleteSelection}, progress::Progress, scraper::{fetch_paper_with_progress, paper_url, save_paper_with_progress, RequestError}, throttle::{set_schedule, Schedule}};
 
 #[derive(Debug)]
 pub struct DownloadConfiguration {
     pub config: Configuration,
     pub output_folder: PathBuf,
+    /// Where papers go inside `output_folder`.
+    pub layout: Layout,
     pub threads: u8,
+    pub metadata: bool,
+    /// Emit one JSON progress event per line on stdout instead of drawing bars.
+    pub progress_json: bool,
+    /// Only print what would be downloaded.
+    pub dry_run: bool,
+    /// Make the library match the configuration, moving downloaded papers no longer selected to the trash.
+    pub sync: bool,
+    /// Don't ask before moving files to the trash.
+    pub yes: bool,
+    /// Write the papers into archives instead of the library.
+    pub archive: Option<ArchiveOptions>,
+    /// Bandwidth and time windows, from the command line or else the configuration.
+    pub schedule: Schedule,
 }
 #[derive(Debug)]
 pub enum DownloadError {
     ConfigNotFound,
     ConfigParseError(std::io::Error),
+    /// Validation found this many errors, they have been reported already.
+    ConfigInvalid(usize),
     DownloadFolderCannotBeCreated,
+    InvalidLayout(String),
 }
 
 impl DownloadConfiguration {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: PathBuf,
         output_folder: PathBuf,
+        format: Option<ConfigFormat>,
+        profile: Option<String>,
+        layout: Option<Layout>,
         threads: u8,
+        metadata: bool,
+        progress_json: bool,
+        dry_run: bool,
+        sync: bool,
+        yes: bool,
+        archive: Option<ArchiveOptions>,
+        schedule: Schedule,
     ) -> Result<DownloadConfiguration, DownloadError> {
         // Make sure config exists
         if !config.exists() {
             return Err(DownloadError::ConfigNotFound);
         }
-        let file = match File::open(&config) {
-            Ok(file) => file,
-            Err(_) => return Err(DownloadError::ConfigNotFound),
+        let diagnostics = match validate_file(&config, format) {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => return Err(DownloadError::ConfigParseError(e)),
         };
+        let errors = report(&diagnostics);
+        if errors > 0 {
+            return Err(DownloadError::ConfigInvalid(errors));
+        }
 
-        // Make sure output folder exists, if not create it
-        if !output_folder.exists() {
-            match std::fs::create_dir_all(&output_folder) {
-                Ok(_) => {
-                    return Ok(DownloadConfiguration {
-                        config: match Configuration::try_from(file) {
-                            Ok(config) => config,
-                            Err(e) => return Err(DownloadError::ConfigParseError(e)),
-                        },
-                        output_folder,
-                        threads,
-                    });
+        let config = match Configuration::load_profile(&config, format, profile.as_deref()) {
+            Ok(config) => config,
+            Err(e) => return Err(DownloadError::ConfigParseError(e)),
+        };
+        let recorded = Layout::recorded(&output_folder).map_err(DownloadError::InvalidLayout)?;
+        let layout = match layout.or(config.layout.clone()) {
+            Some(layout) => {
+                if recorded.as_ref().is_some_and(|x| *x != layout) {
+                    warn!(
+                        "The library was downloaded with layout \"{}\", papers will now be saved as \"{}\".",
+                        recorded.unwrap(),
+                        layout
+                    );
                 }
-                Err(_) => return Err(DownloadError::DownloadFolderCannotBeCreated),
+                layout
             }
+            None => recorded.unwrap_or_default(),
+        };
+
+        // Make sure output folder exists, if not create it. A dry run leaves the disk alone.
+        if !output_folder.exists() && !dry_run && std::fs::create_dir_all(&output_folder).is_err() {
+            return Err(DownloadError::DownloadFolderCannotBeCreated);
         }
         Ok(DownloadConfiguration {
-            config: match Configuration::try_from(file) {
-                Ok(config) => config,
-                Err(e) => return Err(DownloadError::ConfigParseError(e)),
-            },
             threads,
             output_folder,
+            layout,
+            metadata,
+            progress_json,
+            dry_run,
+            sync,
+            yes,
+            archive,
+            schedule: schedule.or(Schedule {
+                bandwidth: config.bandwidth.clone(),
+                windows: config.windows.clone(),
+            }),
+            config,
         })
     }
 }
 
+/// The papers a configuration downloads: paper types filtered and rules expanded.
+/// Fails with the partial selection when a listing couldn't be fetched.
+pub fn select_papers(
+    config: &Configuration,
+    rt: &tokio::runtime::Runtime,
+    threads: u8,
+) -> Result<Configuration, IncompleteSelection> {
+    // Rules pick their paper types while resolving, only the listed papers need filtering.
+    let mut selected = config.clone();
+    let dropped = selected.filter_paper_types();
+    if dropped > 0 {
+        info!("Skipping {} papers of unselected types.", dropped);
+    }
+    resolve_rules(&selected, rt, threads)
+}
+
+/// One paper to fetch and where it goes.
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub syllabus_code: SyllabusCode,
+    pub paper: Paper,
+    pub path: PathBuf,
+}
+
+/// Orders the jobs round-robin across subjects, so a large subject can't hold the others back.
+pub fn interleave(queues: Vec<Vec<DownloadJob>>) -> Vec<DownloadJob> {
+    let mut queues = queues.into_iter().map(|x| x.into_iter()).collect::<Vec<_>>();
+    let mut jobs = vec![];
+    loop {
+        let before = jobs.len();
+        jobs.extend(queues.iter_mut().filter_map(|x| x.next()));
+        if jobs.len() == before {
+            return jobs;
+        }
+    }
+}
+
+/// Creates the subject and year folders of every job up front.
+fn create_folders(jobs: &[DownloadJob]) {
+    let folders = jobs
+        .iter()
+        .filter_map(|x| x.path.parent())
+        .collect::<std::collections::HashSet<_>>();
+    for folder in folders {
+        if let Err(e) = std::fs::create_dir_all(folder) {
+            error!("Failed to create folder {:?}: {}", folder, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// One queue of jobs per subject.
+pub fn subject_jobs(selected: &Configuration, layout: &Layout, output_folder: &Path) -> Vec<Vec<DownloadJob>> {
+    selected
+        .subjects
+        .iter()
+        .filter(|subject| !subject.papers.is_empty())
+        .map(|subject| {
+            // A paper listed twice is fetched once.
+            let mut papers: Vec<&Paper> = vec![];
+            for paper in &subject.papers {
+                if !papers.contains(&paper) {
+                    papers.push(paper);
+                }
+            }
+            papers
+                .into_iter()
+                .map(|paper| DownloadJob {
+                    syllabus_code: subject.syllabus_code.clone(),
+                    paper: paper.clone(),
+                    path: layout.path(output_folder, &subject.syllabus_code, paper),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Destinations the layout gives to more than one paper, with the papers sharing them.
+/// A paper listed twice doesn't collide with itself.
+pub fn collisions<'a>(jobs: impl IntoIterator<Item = &'a DownloadJob>) -> Vec<(&'a Path, Vec<&'a DownloadJob>)> {
+    let mut destinations: HashMap<&Path, Vec<&DownloadJob>> = HashMap::new();
+    for job in jobs {
+        let sharing = destinations.entry(job.path.as_path()).or_default();
+        if !sharing
+            .iter()
+            .any(|x| x.syllabus_code.syllabus_code == job.syllabus_code.syllabus_code && x.paper == job.paper)
+        {
+            sharing.push(job);
+        }
+    }
+    let mut collisions = destinations.into_iter().filter(|(_, x)| x.len() > 1).collect::<Vec<_>>();
+    collisions.sort_by_key(|(x, _)| *x);
+    collisions
+}
+
+/// Prints what a download would do, without touching the network or the disk.
+/// `locate` gives where a paper is saved, whether it is present there and whether something else is in the way.
+fn print_plan(queues: &[Vec<DownloadJob>], mut locate: impl FnMut(&DownloadJob) -> (String, bool, bool)) {
+    let colliding = collisions(queues.iter().flatten())
+        .into_iter()
+        .map(|(x, _)| x)
+        .collect::<Vec<_>>();
+
+    let (mut total, mut present, mut conflicts) = (0, 0, 0);
+    for queue in queues {
+        let (mut subject_present, mut subject_conflicts) = (0, 0);
+        for job in queue {
+            let (destination, exists, blocked) = locate(job);
+            let conflict = colliding.contains(&job.path.as_path()) || blocked;
+            let status = match (conflict, exists) {
+                (true, _) => "conflict",
+                (false, true) => "exists",
+                (false, false) => "new",
+            };
+            subject_conflicts += conflict as usize;
+            subject_present += exists as usize;
+            println!("{:<8} {} -> {}", status, paper_url(&job.syllabus_code, &job.paper), destination);
+        }
+        let syllabus_code = &queue[0].syllabus_code;
+        println!(
+            "{} ({}): {} papers, {} already present, {} to download{}",
+            syllabus_code.name,
+            syllabus_code.syllabus_code,
+            queue.len(),
+            subject_present,
+            queue.len() - subject_present,
+            match subject_conflicts {
+                0 => String::new(),
+                x => format!(", {} conflicting destinations", x),
+            }
+        );
+        total += queue.len();
+        present += subject_present;
+        conflicts += subject_conflicts;
+    }
+    println!(
+        "Total: {} papers, {} already present, {} to download.",
+        total,
+        present,
+        total - present
+    );
+    if conflicts > 0 {
+        warn!(
+            "{} papers share a destination with another paper or a folder, only one of them can be kept.",
+            conflicts
+        );
+    }
+}
+
+/// Syllabus codes the configuration selects that came back without a single paper.
+/// An empty listing is more likely a hiccup of the website than a subject with nothing left.
+fn empty_subjects(config: &Configuration, queues: &[Vec<DownloadJob>]) -> HashSet<String> {
+    let listed = config
+        .subjects
+        .iter()
+        .map(|x| x.syllabus_code.syllabus_code.clone())
+        .chain(
+            config
+                .rules
+                .iter()
+                .flat_map(|x| x.subjects.iter())
+                .filter_map(|x| SyllabusCode::find(x).map(|y| y.syllabus_code)),
+        );
+    listed
+        .filter(|x| !queues.iter().flatten().any(|y| y.syllabus_code.syllabus_code == *x))
+        .collect()
+}
+
+/// Downloaded papers the selection no longer contains. Entries whose file is gone are dropped from the manifest.
+/// Papers of subjects the configuration selects but that came back empty are never stale.
+fn stale_files(
+    manifest: &mut Manifest,
+    root: &Path,
+    config: &Configuration,
+    queues: &[Vec<DownloadJob>],
+) -> Vec<ManifestEntry> {
+    manifest.files.retain(|x| library_path(root, &x.path).is_file());
+    let selected = queues
+        .iter()
+        .flatten()
+        .filter_map(|x| relative_path(root, &x.path))
+        .collect::<HashSet<_>>();
+    let empty = empty_subjects(config, queues);
+    let mut kept = HashSet::new();
+    let stale = manifest
+        .files
+        .iter()
+        .filter(|x| !selected.contains(&x.path))
+        .filter(|x| match empty.contains(&x.syllabus_code) {
+            true => {
+                kept.insert(x.syllabus_code.clone());
+                false
+            }
+            false => true,
+        })
+        .cloned()
+        .collect();
+    for code in kept {
+        warn!("No papers were selected for {}, keeping the papers downloaded for it.", code);
+    }
+    stale
+}
+
+fn confirm(question: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        error!("Not asking for confirmation without a terminal, pass --yes to go ahead.");
+        std::process::exit(1);
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Leaves only the missing papers queued and moves the papers no longer selected to the trash,
+/// after showing what will happen. Returns false when the user backs out.
+fn sync_library(config: &DownloadConfiguration, manifest: &mut Manifest, queues: &mut Vec<Vec<DownloadJob>>) -> bool {
+    let root = &config.output_folder;
+    let stale = stale_files(manifest, root, &config.config, queues);
+    let total = queues.iter().map(Vec::len).sum::<usize>();
+    for queue in queues.iter_mut() {
+        queue.retain(|x| !x.path.is_file());
+    }
+    queues.retain(|x| !x.is_empty());
+    let missing = queues.iter().map(Vec::len).sum::<usize>();
+
+    let trash = trash_folder(root);
+    let mut summary = format!("Syncing {:?} with the configuration:\n", root);
+    summary += &format!("  {} papers selected, {} already present, {} to download\n", total, total - missing, missing);
+    summary += &format!("  {} papers no longer selected, moved to {:?}\n", stale.len(), trash);
+    for entry in &stale {
+        summary += &format!("    {}\n", entry.path);
+    }
+    // Stdout carries only JSON events with --progress-json.
+    match config.progress_json {
+        true => eprint!("{}", summary),
+        false => print!("{}", summary),
+    }
+    if stale.is_empty() {
+        return true;
+    }
+    if !config.yes && !confirm("Continue?") {
+        info!("Sync cancelled, nothing was changed.");
+        return false;
+    }
+
+    let mut moved = 0;
+    for entry in stale {
+        match move_to_trash(root, &trash, &entry.path) {
+            Ok(_) => {
+                manifest.remove(&entry.path);
+                moved += 1;
+            }
+            Err(e) => error!("Failed to move {} to the trash: {}", entry.path, e),
+        }
+    }
+    if let Err(e) = manifest.save(root) {
+        error!("Failed to save the library manifest: {}", e);
+        std::process::exit(1);
+    }
+    info!("Moved {} papers to {:?}, move them back to restore them.", moved, trash);
+    true
+}
+
 pub fn handle_download(config: DownloadConfiguration) {
-    config.config.subjects.iter().for_each(|subject| {
-        info!(
-            "Downloading papers for subject: {}",
-            format!(
-                "{} ({})",
-                subject.syllabus_code.name, subject.syllabus_code.syllabus_code
-            )
+    if config.dry_run {
+        // Rules need the live listing, a dry run stays offline.
+        let mut selected = config.config.clone();
+        selected.filter_paper_types();
+        if !selected.rules.is_empty() {
+            warn!(
+                "{} selection rules need the live listing and are not expanded in a dry run.",
+                selected.rules.len()
+            );
+        }
+        let queues = subject_jobs(&selected, &config.layout, &config.output_folder);
+        match config.archive {
+            Some(options) => {
+                // Without --append the archives are replaced, nothing in them counts.
+                let mut archived: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+                print_plan(&queues, |job| {
+                    let (archive, name) = destination(&config.output_folder, options, job);
+                    let present = archived.entry(archive.clone()).or_insert_with(|| match options.append && archive.exists() {
+                        true => entries(&archive, options.format).unwrap_or_else(|e| {
+                            warn!("Failed to read archive {:?}: {}", archive, e);
+                            HashSet::new()
+                        }),
+                        false => HashSet::new(),
+                    });
+                    (format!("{}:{}", archive.display(), name), present.contains(&name), false)
+                });
+            }
+            None => print_plan(&queues, |job| (job.path.display().to_string(), job.path.is_file(), job.path.is_dir())),
+        }
+        if config.sync {
+            let mut manifest = Manifest::load(&config.output_folder).unwrap_or_default();
+            for entry in stale_files(&mut manifest, &config.output_folder, &selected, &queues) {
+                println!("{:<8} {}", "trash", library_path(&config.output_folder, &entry.path).display());
+            }
+        }
+        return;
+    }
+
+    let mut manifest = match Manifest::load(&config.output_folder) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!("Failed to read the library manifest: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Shared by the listings and the downloads.
+    let rt = runtime(config.threads);
+    let resolved = match select_papers(&config.config, &rt, config.threads) {
+        Ok(resolved) => resolved,
+        // A missing listing would look like papers no longer selected.
+        Err(e) if config.sync => {
+            error!(
+                "{} listings could not be fetched, not syncing so their papers aren't moved to the trash.",
+                e.failed
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            warn!("{} listings could not be fetched, their papers are skipped.", e.failed);
+            *e.resolved
+        }
+    };
+    let mut queues = subject_jobs(&resolved, &config.layout, &config.output_folder);
+    let colliding = collisions(queues.iter().flatten());
+    if !colliding.is_empty() {
+        for (path, jobs) in &colliding {
+            let papers = jobs
+                .iter()
+                .map(|x| x.paper.get_ref_filename(&x.syllabus_code))
+                .collect::<Vec<_>>();
+            error!("{} would all be saved to {:?}", papers.join(", "), path);
+        }
+        error!(
+            "Layout \"{}\" saves different papers to the same path, nothing was downloaded.",
+            config.layout
         );
-        // make folder for the subject
-        let subject_folder = config.output_folder.join(format!(
-            "{} ({})",
-            subject.syllabus_code.name, subject.syllabus_code.syllabus_code
-        ));
-        if !subject_folder.exists() {
-            match std::fs::create_dir_all(&subject_folder) {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("Failed to create folder for subject: {}", e);
-                    std::process::exit(1);
-                }
+        std::process::exit(1);
+    }
+    if config.sync && !sync_library(&config, &mut manifest, &mut queues) {
+        return;
+    }
+    let archives = match config.archive {
+        Some(options) => match Archives::open(&config.output_folder, options, &queues.concat()) {
+            Ok(archives) => Some(Arc::new(archives)),
+            Err(e) => {
+                error!("Failed to open archive {}", e);
+                std::process::exit(1);
             }
+        },
+        None => None,
+    };
+    if let Some(archives) = &archives {
+        let total = queues.iter().map(Vec::len).sum::<usize>();
+        for queue in queues.iter_mut() {
+            queue.retain(|x| !archives.contains(x));
         }
-        let years = &subject
-            .papers
-            .iter()
-            .map(|paper| paper.year.clone())
-            .collect::<std::collections::HashSet<String>>();
-
-        years.iter().for_each(|year| {
-            // create folder
-            let year_folder = subject_folder.join(year);
-            if !year_folder.exists() {
-                match std::fs::create_dir_all(&year_folder) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Failed to create folder for year: {}", e);
-                        std::process::exit(1);
+        queues.retain(|x| !x.is_empty());
+        let archived = total - queues.iter().map(Vec::len).sum::<usize>();
+        if archived > 0 {
+            info!("Skipping {} papers the archives already hold.", archived);
+        }
+    }
+    for queue in &queues {
+        info!(
+            "Queueing {} papers for subject: {} ({})",
+            queue.len(),
+            queue[0].syllabus_code.name,
+            queue[0].syllabus_code.syllabus_code
+        );
+    }
+    let jobs = interleave(queues);
+    set_schedule(config.schedule.clone());
+    // Archived papers never reach the library, it stays as it was.
+    if archives.is_none() {
+        create_folders(&jobs);
+        if let Err(e) = config.layout.record(&config.output_folder) {
+            warn!("Failed to record the library layout: {}", e);
+        }
+    }
+
+    let total = jobs.len();
+    let metadata = config.metadata;
+    let progress = Progress::new(config.progress_json, &jobs);
+    // Per file log lines would tear the bars, warnings and errors still get through.
+    let log_level = log::max_level();
+    if progress.is_interactive() {
+        log::set_max_level(log_level.min(log::LevelFilter::Warn));
+    }
+    let progress = &progress;
+    let archives_ref = archives.as_ref();
+    let started = std::time::Instant::now();
+    // At most `threads` papers are in flight, taken from the queue in order.
+    let results = rt.block_on(
+        stream::iter(jobs)
+            .map(|job| async move {
+                progress.started(&job);
+                let mut last = 0;
+                let on_progress = |downloaded, total| {
+                    progress.advanced(&job, downloaded - last, downloaded, total);
+                    last = downloaded;
+                };
+                let saved = match archives_ref {
+                    Some(archives) => match fetch_paper_with_progress(&job.syllabus_code, &job.paper, on_progress).await {
+                        Ok(body) => {
+                            let size = body.len() as u64;
+                            // Compressing and writing blocks, it runs off the workers driving the other downloads.
+                            let (archives, added) = (archives.clone(), job.clone());
+                            let added = tokio::task::spawn_blocking(move || archives.add(&added, &body))
+                                .await
+                                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+                            match added {
+                                Ok(_) => Ok(size),
+                                Err(e) => {
+                                    error!("Failed to add {:?} to its archive: {}", job.path, e);
+                                    Err(RequestError::TokioError(e))
+                                }
+                            }
+                        }
+                        Err(e) => Err(e),
+                    },
+                    None => save_paper_with_progress(&job.syllabus_code, &job.paper, &job.path, on_progress).await,
+                };
+                // Optional post-download step, failures leave the downloaded file as is.
+                if saved.is_ok() && metadata {
+                    let written = job.clone();
+                    let applied = tokio::task::spawn_blocking(move || {
+                        apply_metadata(&written.syllabus_code, &written.paper, &written.path)
+                    })
+                    .await;
+                    match applied {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => warn!("Failed to write metadata to {:?}: {}", job.path, e),
+                        Err(e) => warn!("Failed to write metadata to {:?}: {}", job.path, e),
                     }
                 }
-            }
-        });
-        // cluster papers and async download it and save it.
-        let rt = tokio::runtime::Builder::new_multi_thread()
-                    .worker_threads(config.threads as usize)
-                    .enable_all()
-                    .build();
-
-        let rt = match rt {
-            Ok(rt) => rt,
+                match &saved {
+                    Ok(bytes) => progress.finished(&job, *bytes),
+                    Err(e) => progress.failed(&job, format!("{:?}", e)),
+                }
+                if let (Err(_), Some(archives)) = (&saved, archives_ref) {
+                    archives.failed(&job);
+                }
+                (job, saved)
+            })
+            .buffer_unordered(config.threads.max(1) as usize)
+            .collect::<Vec<_>>(),
+    );
+    progress.finish();
+    log::set_max_level(log_level);
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let bytes = results.iter().filter_map(|(_, x)| x.as_ref().ok()).sum::<u64>();
+    let failed = results.iter().filter(|(_, x)| x.is_err()).count();
+
+    // Only what this tool wrote is ever pruned by a later sync, the checksums let `verify` spot damage.
+    for (job, _) in results.iter().filter(|(_, x)| x.is_ok() && config.archive.is_none()) {
+        let (size, sha256) = match fingerprint(&job.path) {
+            Ok(fingerprint) => fingerprint,
             Err(e) => {
-                error!("Failed to create tokio runtime: {}", e);
-                std::process::exit(1);
+                warn!("Failed to read back {:?}: {}", job.path, e);
+                continue;
             }
         };
-        let papers = subject.papers.iter().map(|paper| {
-            (paper.clone(), subject.clone(), subject_folder.clone())
-        }).collect::<Vec<_>>();
-        // chunk papers into threads 
-        rt.block_on(async {
-            stream::iter(papers)
-            .par_then(None, |val| async move {
-                save_paper(
-                    &val.1.syllabus_code,
-                    &val.0,
-                    &val.2.join(&val.0.year).join(Paper::get_ref_filename(&val.0, &val.1.syllabus_code)),
-                )
-                .await;
-            })
-            .collect::<Vec<_>>()
-            .await;
-        });
-
-        // rt.block_on(async {
-        //     futures::stream::iter(papers)
-        //         .map(|paper| async move {
-        //             save_paper(
-        //                 &paper.1.syllabus_code, 
-        //                 &paper.0, 
-        //                 &paper.2.join(&paper.0.year).join(Paper::get_ref_filename(&paper.0, &paper.1.syllabus_code))
-        //             ).await
-        //         })
-        //         .buffer_unordered(config.threads as usize)
-        //         .collect::<Vec<_>>().await
-        //     }
-        // );
-    });
+        if let Some(path) = relative_path(&config.output_folder, &job.path) {
+            manifest.record(ManifestEntry {
+                path,
+                syllabus_code: job.syllabus_code.syllabus_code.clone(),
+                access_slug: job.syllabus_code.access_slug.clone(),
+                file: job.paper.get_ref_filename(&job.syllabus_code),
+                size,
+                sha256,
+            });
+        }
+    }
+    if total > failed && config.archive.is_none() {
+        if let Err(e) = manifest.save(&config.output_folder) {
+            warn!("Failed to save the library manifest: {}", e);
+        }
+    }
+    let megabytes = bytes as f64 / 1_000_000.0;
+    info!(
+        "Downloaded {} of {} papers, {:.1} MB in {:.1}s ({:.2} MB/s).",
+        total - failed,
+        total,
+        megabytes,
+        elapsed,
+        megabytes / elapsed.max(0.001)
+    );
+    if failed > 0 {
+        warn!("{} papers failed to download.", failed);
+    }
+    // Every download is done, so nothing else holds the archives any more.
+    if let Some(archives) = archives.and_then(Arc::into_inner) {
+        let (written, failed) = archives.finish();
+        for path in written {
+            info!("Wrote archive {:?}", path);
+        }
+        if failed > 0 {
+            std::process::exit(1);
+        }
+    }
 }
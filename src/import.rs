@@ -0,0 +1,159 @@
+use std::{io::Read, path::PathBuf, str::FromStr};
+
+use crate::{
+    config_format::{write_atomically, ConfigFormat},
+    config_validate::{has_errors, validate},
+    configuration::{Configuration, Paper, SyllabusCode, YearConfiguration, CONFIG_VERSION, SYLLABUS_CODES},
+};
+
+#[derive(Debug)]
+pub struct ImportConfiguration {
+    /// List to read, stdin when missing or `-`.
+    pub input: Option<PathBuf>,
+    pub output: PathBuf,
+    pub format: Option<ConfigFormat>,
+}
+
+/// Turns one reference into a canonical filename stem, e.g. `9709_s23_qp_12`.
+/// Accepts filenames, URLs and loose forms such as `9702 w22 ms 42` or `9709-s23-qp12.pdf`.
+fn normalize(reference: &str) -> Result<String, String> {
+    // URLs and paths name the paper in their last segment.
+    let reference = reference
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(reference)
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(reference);
+    let reference = reference.to_lowercase();
+    let reference = reference.strip_suffix(".pdf").unwrap_or(&reference);
+    let parts = reference
+        .split(|x: char| x.is_whitespace() || x == '_' || x == '-')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+
+    let matcher = regex::Regex::new(r"^(\d{4})_([msw])(\d{2})_(qp|ms|er|in|gt|ir|ci)(?:_?(\d{1,2}))?$").unwrap();
+    let captures = match matcher.captures(&parts) {
+        Some(captures) => captures,
+        None => {
+            return Err("expected <code> <session><yy> <type> [paper], e.g. 9709_s23_qp_12".to_string());
+        }
+    };
+    let (code, season, year, paper_type) = (&captures[1], &captures[2], &captures[3], &captures[4]);
+    match (paper_type, captures.get(5)) {
+        ("er" | "gt", Some(_)) => Err(format!("{} covers every paper, drop the paper number", paper_type)),
+        ("er" | "gt", None) => Ok(format!("{}_{}{}_{}", code, season, year, paper_type)),
+        (_, None) => Err(format!("{} needs a paper number, e.g. {}_{}{}_{}_12", paper_type, code, season, year, paper_type)),
+        (_, Some(variant)) => Ok(format!("{}_{}{}_{}_{}", code, season, year, paper_type, variant.as_str())),
+    }
+}
+
+/// Parses one line of an import list into its syllabus and paper.
+pub fn parse_reference(reference: &str) -> Result<(SyllabusCode, Paper), String> {
+    let stem = normalize(reference)?;
+    let code = &stem[..4];
+    let syllabus_code = match SYLLABUS_CODES.iter().find(|x| x.syllabus_code == code) {
+        Some(syllabus_code) => syllabus_code.clone(),
+        None => return Err(format!("unknown syllabus code {}", code)),
+    };
+    match Paper::from_str(&stem) {
+        Ok(paper) => Ok((syllabus_code, paper)),
+        Err(e) => Err(format!("failed to parse {}: {:?}", stem, e)),
+    }
+}
+
+pub fn handle_import(config: ImportConfiguration) {
+    let mut list = String::new();
+    let read = match &config.input {
+        Some(input) if input.as_os_str() != "-" => std::fs::read_to_string(input).map(|x| list = x),
+        _ => std::io::stdin().read_to_string(&mut list).map(|_| ()),
+    };
+    if let Err(e) = read {
+        error!("Failed to read paper list: {}", e);
+        std::process::exit(1);
+    }
+
+    let mut imported = Configuration {
+        version: CONFIG_VERSION,
+        papers: vec![],
+        rules: vec![],
+        subjects: vec![],
+        layout: None,
+        bandwidth: None,
+        windows: vec![],
+    };
+    let mut failed = 0;
+    let mut count = 0;
+    for (number, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (syllabus_code, paper) = match parse_reference(line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                error!("Line {}: \"{}\": {}", number + 1, line, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if !imported.papers.contains(&paper.paper_type) {
+            imported.papers.push(paper.paper_type.clone());
+        }
+        let subject = match imported
+            .subjects
+            .iter()
+            .position(|x| x.syllabus_code.access_slug == syllabus_code.access_slug)
+        {
+            Some(i) => &mut imported.subjects[i],
+            None => {
+                imported.subjects.push(YearConfiguration {
+                    syllabus_code,
+                    paper_types: None,
+                    papers: vec![],
+                    known: None,
+                });
+                imported.subjects.last_mut().unwrap()
+            }
+        };
+        if subject.papers.contains(&paper) {
+            debug!("Line {}: \"{}\" is already listed.", number + 1, line);
+            continue;
+        }
+        subject.papers.push(paper);
+        count += 1;
+    }
+    imported.papers.sort();
+
+    if count == 0 {
+        error!("No papers imported, {} lines failed.", failed);
+        std::process::exit(1);
+    }
+    let format = ConfigFormat::resolve(config.format, &config.output);
+    let serialized = match format.serialize(&imported) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            error!("Failed to serialize configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if has_errors(&validate(&serialized, format)) {
+        error!("Imported configuration failed validation.");
+        std::process::exit(1);
+    }
+    if let Err(e) = write_atomically(&config.output, &serialized) {
+        error!("Failed to write configuration file: {}", e);
+        std::process::exit(1);
+    }
+    info!(
+        "Imported {} papers of {} subjects into {:?}.",
+        count,
+        imported.subjects.len(),
+        config.output
+    );
+    if failed > 0 {
+        warn!("{} lines could not be imported.", failed);
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    configuration::{Paper, PaperType, Season, SyllabusCode, SYLLABUS_CODES},
+    library::METADATA_DIR,
+};
+
+/// The layout `handle_download` has always written.
+pub const DEFAULT_LAYOUT: &str = "{subject} ({code})/{year}/{filename}";
+
+/// File in the metadata folder recording the layout a library was downloaded with.
+const LAYOUT_FILE: &str = "layout";
+
+/// A placeholder of a layout template, filled from the `Paper` and its `SyllabusCode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Subject name, e.g. "Mathematics".
+    Subject,
+    /// Syllabus code, e.g. "9709".
+    Code,
+    /// e.g. "2023".
+    Year,
+    /// Last two digits of the year, e.g. "23".
+    Yy,
+    /// Season letter, e.g. "s".
+    Season,
+    /// e.g. "Summer".
+    SeasonName,
+    /// Season letter and two digit year, e.g. "s23".
+    Session,
+    /// e.g. "qp".
+    Type,
+    /// e.g. "12", empty for examiner reports and grade thresholds.
+    Variant,
+    /// Paper number, the first digit of the variant, e.g. "1".
+    Component,
+    /// The file name on the website, e.g. "9709_s23_qp_12.pdf".
+    Filename,
+}
+
+const FIELDS: [(&str, Field); 11] = [
+    ("subject", Field::Subject),
+    ("code", Field::Code),
+    ("year", Field::Year),
+    ("yy", Field::Yy),
+    ("season", Field::Season),
+    ("season_name", Field::SeasonName),
+    ("session", Field::Session),
+    ("type", Field::Type),
+    ("variant", Field::Variant),
+    ("component", Field::Component),
+    ("filename", Field::Filename),
+];
+
+/// Fields that tell papers apart, a layout without one of each group can give several papers the same path.
+/// Subject names don't tell syllabi apart, e.g. Computer Science is both 9608 and 9618.
+const IDENTITY: [(&str, &[Field]); 5] = [
+    ("syllabus code", &[Field::Code, Field::Filename]),
+    ("year", &[Field::Year, Field::Yy, Field::Session, Field::Filename]),
+    ("season", &[Field::Season, Field::SeasonName, Field::Session, Field::Filename]),
+    ("paper type", &[Field::Type, Field::Filename]),
+    ("variant", &[Field::Variant, Field::Filename]),
+];
+
+impl Field {
+    fn name(&self) -> &'static str {
+        FIELDS.iter().find(|(_, x)| x == self).map(|(x, _)| *x).unwrap_or("")
+    }
+
+    fn value(&self, syllabus_code: &SyllabusCode, paper: &Paper) -> String {
+        let yy = paper.year.get(paper.year.len().saturating_sub(2)..).unwrap_or("");
+        match self {
+            Field::Subject => syllabus_code.name.clone(),
+            Field::Code => syllabus_code.syllabus_code.clone(),
+            Field::Year => paper.year.clone(),
+            Field::Yy => yy.to_string(),
+            Field::Season => paper.season.to_string(),
+            Field::SeasonName => format!("{:?}", paper.season),
+            Field::Session => format!("{}{}", paper.season, yy),
+            Field::Type => paper.paper_type.to_string(),
+            Field::Variant => paper.variant.clone(),
+            Field::Component => paper.component().to_string(),
+            Field::Filename => paper.get_ref_filename(syllabus_code),
+        }
+    }
+
+    /// What the field matches when reading a path back.
+    fn pattern(&self) -> &'static str {
+        match self {
+            Field::Subject => r"[^/]+?",
+            Field::Code | Field::Year => r"\d{4}",
+            Field::Yy => r"\d{2}",
+            Field::Season => r"[msw]",
+            Field::SeasonName => r"Winter|Summer|March",
+            Field::Session => r"[msw]\d{2}",
+            Field::Type => r"qp|ms|er|in|gt|ir|ci",
+            Field::Variant => r"\d*",
+            Field::Component => r"\d?",
+            Field::Filename => r"\d{4}_[msw]\d{2}_(?:qp|ms|er|in|gt|ir|ci)(?:_\d+)?\.pdf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// Where papers live inside a library, e.g. `{code}/{session}/{component}/{type}.pdf`.
+/// Paths are separated by `/` whatever the platform.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Layout {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::from_str(DEFAULT_LAYOUT).unwrap()
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+/// The placeholders a template may use, for help messages.
+pub fn field_names() -> String {
+    FIELDS.iter().map(|(x, _)| format!("{{{}}}", x)).collect::<Vec<_>>().join(", ")
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(0) if rest.starts_with('}') => return Err(format!("Unmatched \"}}\" in layout \"{}\"", template)),
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .ok_or(format!("Unclosed \"{{\" in layout \"{}\"", template))?;
+                    let name = &rest[1..end];
+                    let field = FIELDS.iter().find(|(x, _)| *x == name).map(|(_, x)| *x).ok_or(format!(
+                        "Unknown field {{{}}} in layout \"{}\", available fields: {}",
+                        name,
+                        template,
+                        field_names()
+                    ))?;
+                    segments.push(Segment::Field(field));
+                    rest = &rest[end + 1..];
+                }
+                Some(i) => {
+                    segments.push(Segment::Literal(rest[..i].to_string()));
+                    rest = &rest[i..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        if template.starts_with('/') || template.contains('\\') {
+            return Err(format!("Layout \"{}\" must be a relative path separated by \"/\"", template));
+        }
+        if template.ends_with('/') {
+            return Err(format!("Layout \"{}\" must end with a file name", template));
+        }
+        for part in template.split('/') {
+            if part.is_empty() || part == "." || part == ".." || part == METADATA_DIR {
+                return Err(format!("Layout \"{}\" has an invalid path component \"{}\"", template, part));
+            }
+        }
+
+        Ok(Layout {
+            template: template.to_string(),
+            segments,
+        })
+    }
+}
+
+impl TryFrom<String> for Layout {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Layout::from_str(&value)
+    }
+}
+
+impl From<Layout> for String {
+    fn from(value: Layout) -> Self {
+        value.template
+    }
+}
+
+impl Layout {
+    fn fields(&self) -> impl Iterator<Item = Field> + '_ {
+        self.segments.iter().filter_map(|x| match x {
+            Segment::Field(x) => Some(*x),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// What papers may differ by and still get the same path, with the fields that would tell them apart.
+    /// e.g. `{code}/{session}/{component}/{type}.pdf` only works while one variant per component is selected.
+    pub fn ambiguities(&self) -> Vec<(&'static str, String)> {
+        IDENTITY
+            .iter()
+            .filter(|(_, fields)| !fields.iter().any(|x| self.fields().any(|y| y == *x)))
+            .map(|(what, fields)| {
+                let names = fields.iter().map(|x| format!("{{{}}}", x.name())).collect::<Vec<_>>();
+                (*what, names.join(", "))
+            })
+            .collect()
+    }
+
+    /// Where a paper is saved below `root`.
+    pub fn path(&self, root: &Path, syllabus_code: &SyllabusCode, paper: &Paper) -> PathBuf {
+        let relative = self
+            .segments
+            .iter()
+            .map(|x| match x {
+                Segment::Literal(x) => x.clone(),
+                // Values never open new folders.
+                Segment::Field(x) => x.value(syllabus_code, paper).replace(['/', '\\'], "-"),
+            })
+            .collect::<String>();
+        relative.split('/').fold(root.to_path_buf(), |path, x| path.join(x))
+    }
+
+    /// Reads the syllabus code and paper back from a path relative to the library root.
+    /// Returns `None` when the path doesn't follow this layout.
+    pub fn parse(&self, relative: &Path) -> Option<(String, Paper)> {
+        let relative = relative
+            .components()
+            .map(|x| x.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?
+            .join("/");
+        let mut pattern = String::new();
+        let mut folder = false;
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(x) if folder => {
+                    pattern += "/)?";
+                    pattern += &regex::escape(&x[1..]);
+                    folder = false;
+                }
+                Segment::Literal(x) => pattern += &regex::escape(x),
+                // `path` leaves out the folder of an empty variant, e.g. `{component}/` of an examiner report.
+                Segment::Field(x @ (Field::Variant | Field::Component))
+                    if (i == 0 || matches!(&self.segments[i - 1], Segment::Literal(y) if y.ends_with('/')))
+                        && matches!(self.segments.get(i + 1), Some(Segment::Literal(y)) if y.starts_with('/')) =>
+                {
+                    pattern += &format!("(?:({})", x.pattern());
+                    folder = true;
+                }
+                Segment::Field(x) => pattern += &format!("({})", x.pattern()),
+            }
+        }
+        let captures = regex::Regex::new(&format!("^{}$", pattern)).ok()?.captures(&relative)?;
+
+        let mut values: Vec<(Field, &str)> = vec![];
+        for (i, field) in self.fields().enumerate() {
+            let value = captures.get(i + 1).map_or("", |x| x.as_str());
+            match values.iter().find(|(x, _)| *x == field) {
+                // A field used twice has to agree with itself.
+                Some((_, existing)) if *existing != value => return None,
+                Some(_) => {}
+                None => values.push((field, value)),
+            }
+        }
+        let get = |field: Field| values.iter().find(|(x, _)| *x == field).map(|(_, x)| *x);
+
+        if let Some(filename) = get(Field::Filename) {
+            let paper = Paper::from_str(filename).ok()?;
+            return Some((filename[..4].to_string(), paper));
+        }
+        let code = match (get(Field::Code), get(Field::Subject)) {
+            (Some(code), _) => code.to_string(),
+            // A name shared by several syllabi can't be read back.
+            (None, Some(name)) => {
+                let mut codes = SYLLABUS_CODES.iter().filter(|x| x.name == name).map(|x| &x.syllabus_code);
+                let code = codes.next()?;
+                if codes.any(|x| x != code) {
+                    return None;
+                }
+                code.clone()
+            }
+            (None, None) => return None,
+        };
+        let session = get(Field::Session);
+        let year = match (get(Field::Year), get(Field::Yy), session) {
+            (Some(year), _, _) => year.to_string(),
+            (None, Some(yy), _) => format!("20{}", yy),
+            (None, None, Some(session)) => format!("20{}", &session[1..]),
+            _ => return None,
+        };
+        // Every form starts with the season letter, "Summer" included.
+        let season = get(Field::Season).or(get(Field::SeasonName)).or(session)?;
+        let season = Season::from_str(&format!("{}00", season[..1].to_lowercase())).ok()?;
+        let paper_type = PaperType::from_str(&format!("_{}", get(Field::Type)?)).ok()?;
+        // Without the variant, the component is all there is to go by.
+        let variant = get(Field::Variant).or(get(Field::Component))?;
+        let has_variant = !matches!(paper_type, PaperType::ER | PaperType::GT);
+        if has_variant == variant.is_empty() {
+            return None;
+        }
+        Some((code, Paper::new(&year, season, paper_type, variant)))
+    }
+
+    /// The layout recorded in a library, if it has one.
+    pub fn recorded(root: &Path) -> Result<Option<Layout>, String> {
+        let path = root.join(METADATA_DIR).join(LAYOUT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let template = std::fs::read_to_string(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Layout::from_str(template.trim())
+            .map(Some)
+            .map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    /// Records this layout in the library, so later commands find the papers.
+    pub fn record(&self, root: &Path) -> Result<(), std::io::Error> {
+        let folder = root.join(METADATA_DIR);
+        std::fs::create_dir_all(&folder)?;
+        std::fs::write(folder.join(LAYOUT_FILE), format!("{}\n", self.template))
+    }
+
+    /// The layout of a library: the one asked for, else the recorded one, else the default.
+    pub fn for_library(requested: Option<&Layout>, root: &Path) -> Result<Layout, String> {
+        match requested {
+            Some(layout) => Ok(layout.clone()),
+            None => Ok(Layout::recorded(root)?.unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mathematics() -> SyllabusCode {
+        SyllabusCode::find("9709").unwrap()
+    }
+
+    fn papers() -> Vec<Paper> {
+        vec![
+            Paper::new("2023", Season::Summer, PaperType::QP, "12"),
+            Paper::new("2021", Season::Winter, PaperType::MS, "43"),
+            Paper::new("2019", Season::March, PaperType::ER, ""),
+            Paper::new("2022", Season::Summer, PaperType::GT, ""),
+        ]
+    }
+
+    /// Saves every paper with `template` and reads it back.
+    fn round_trip(template: &str) {
+        let layout = Layout::from_str(template).unwrap();
+        let root = Path::new("library");
+        for paper in papers() {
+            let path = layout.path(root, &mathematics(), &paper);
+            let relative = path.strip_prefix(root).unwrap();
+            assert_eq!(
+                layout.parse(relative),
+                Some(("9709".to_string(), paper.clone())),
+                "{} read back from {:?}",
+                template,
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn default_layout_round_trips() {
+        round_trip(DEFAULT_LAYOUT);
+        let path = Layout::default().path(Path::new(""), &mathematics(), &papers()[0]);
+        assert_eq!(path, Path::new("Mathematics (9709)/2023/9709_s23_qp_12.pdf"));
+    }
+
+    #[test]
+    fn component_layout_round_trips_up_to_the_component() {
+        let layout = Layout::from_str("{code}/{session}/{component}/{type}.pdf").unwrap();
+        let root = Path::new("library");
+        for paper in papers() {
+            let path = layout.path(root, &mathematics(), &paper);
+            let (code, parsed) = layout.parse(path.strip_prefix(root).unwrap()).unwrap();
+            // Only one variant per component fits this layout, the component stands in for it.
+            let expected = Paper::new(&paper.year, paper.season.clone(), paper.paper_type.clone(), paper.component());
+            assert_eq!((code.as_str(), parsed), ("9709", expected));
+        }
+    }
+
+    #[test]
+    fn subject_names_shared_by_syllabi_are_not_enough() {
+        let layout = Layout::from_str("{subject}/{type}/{year}{season}_{variant}.pdf").unwrap();
+        assert_eq!(
+            layout.ambiguities(),
+            vec![("syllabus code", "{code}, {filename}".to_string())]
+        );
+        let paper = Paper::new("2020", Season::Summer, PaperType::QP, "12");
+        let old = layout.path(Path::new(""), &SyllabusCode::find("9608").unwrap(), &paper);
+        let new = layout.path(Path::new(""), &SyllabusCode::find("9618").unwrap(), &paper);
+        assert_eq!(old, new);
+        // Either syllabus could have written it.
+        assert_eq!(layout.parse(&old), None);
+    }
+
+    #[test]
+    fn layouts_without_filename_round_trip() {
+        round_trip("{subject}/{type}/{year}{season}_{variant}.pdf");
+        round_trip("{code}/{season_name} {yy}/{type}_{variant}.pdf");
+    }
+
+    #[test]
+    fn two_digit_years_are_this_century() {
+        let layout = Layout::from_str("{code}/{yy}/{season}/{type}_{variant}.pdf").unwrap();
+        let (_, paper) = layout.parse(Path::new("9709/23/s/qp_12.pdf")).unwrap();
+        assert_eq!(paper.year, "2023");
+    }
+
+    #[test]
+    fn a_field_used_twice_has_to_agree() {
+        let layout = Layout::from_str("{code}/{year}/{year}{season}_{type}_{variant}.pdf").unwrap();
+        assert!(layout.parse(Path::new("9709/2023/2023s_qp_12.pdf")).is_some());
+        assert!(layout.parse(Path::new("9709/2023/2022s_qp_12.pdf")).is_none());
+    }
+
+    #[test]
+    fn variants_follow_the_paper_type() {
+        let layout = Layout::from_str("{code}/{session}/{type}{variant}.pdf").unwrap();
+        // Examiner reports and grade thresholds have no variant, every other type needs one.
+        assert!(layout.parse(Path::new("9709/s23/er.pdf")).is_some());
+        assert!(layout.parse(Path::new("9709/s23/gt.pdf")).is_some());
+        assert!(layout.parse(Path::new("9709/s23/er12.pdf")).is_none());
+        assert!(layout.parse(Path::new("9709/s23/qp.pdf")).is_none());
+    }
+
+    #[test]
+    fn paths_of_other_layouts_are_not_read() {
+        let layout = Layout::default();
+        assert!(layout.parse(Path::new("9709/s23/1/qp.pdf")).is_none());
+        assert!(layout.parse(Path::new("Mathematics (9709)/2023/notes.txt")).is_none());
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in [
+            "{code}/{year",
+            "{code}/year}/{filename}",
+            "{code}/{month}/{filename}",
+            "/{code}/{filename}",
+            "{code}\\{filename}",
+            "{code}/",
+            "{code}//{filename}",
+            "{code}/../{filename}",
+            ".gce-scraper/{filename}",
+        ] {
+            assert!(Layout::from_str(template).is_err(), "{} was accepted", template);
+        }
+    }
+
+    #[test]
+    fn ambiguities_name_the_missing_fields() {
+        assert!(Layout::default().ambiguities().is_empty());
+        let layout = Layout::from_str("{code}/{session}/{component}/{type}.pdf").unwrap();
+        assert_eq!(layout.ambiguities(), vec![("variant", "{variant}, {filename}".to_string())]);
+    }
+}
@@ -2,7 +2,30 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
+pub mod archive;
+pub mod config_compose;
+pub mod config_export;
+pub mod config_format;
 pub mod config_gen;
+pub mod config_migrate;
+pub mod config_validate;
+pub mod config_wizard;
 pub mod configuration;
+pub mod dedup;
+pub mod defaults;
 pub mod scraper;
-pub mod download;
\ No newline at end of file
+pub mod download;
+pub mod import;
+pub mod layout;
+pub mod library;
+pub mod manifest;
+pub mod pdf;
+pub mod progress;
+pub mod thresholds;
+pub mod throttle;
+pub mod search;
+pub mod split;
+pub mod pack;
+pub mod metadata;
+pub mod rules;
+pub mod verify;
@@ -0,0 +1,100 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    configuration::{Paper, PaperType, SyllabusCode, SYLLABUS_CODES},
+    layout::Layout,
+};
+
+/// Folder inside the library where the scraper keeps its own bookkeeping files.
+pub const METADATA_DIR: &str = ".gce-scraper";
+
+/// A paper found on disk, in the layout produced by `handle_download`.
+#[derive(Debug, Clone)]
+pub struct LibraryPaper {
+    pub path: PathBuf,
+    pub syllabus_code: SyllabusCode,
+    pub paper: Paper,
+}
+
+/// Recursively collects every file below `root`, sorted by path. The metadata folder is skipped.
+pub fn collect_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read directory {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if entry.file_name() != METADATA_DIR {
+                    pending.push(path);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Scans `root` for downloaded papers, recovering the `Paper` and `SyllabusCode` from the path
+/// through `layout`. Files that don't follow it are still found by their original file name.
+pub fn scan_library(root: &Path, layout: &Layout) -> Vec<LibraryPaper> {
+    collect_files(root)
+        .into_iter()
+        .filter_map(|path| {
+            let file_name = path.file_name()?.to_str()?.to_string();
+            if !file_name.ends_with(".pdf") {
+                return None;
+            }
+            let parsed = path.strip_prefix(root).ok().and_then(|x| layout.parse(x));
+            let (code, paper) = match parsed {
+                Some(parsed) => parsed,
+                None => match Paper::from_str(&file_name) {
+                    Ok(paper) => (file_name.split('_').next()?.to_string(), paper),
+                    Err(e) => {
+                        debug!("Skipping {:?}, not a paper: {:?}", path, e);
+                        return None;
+                    }
+                },
+            };
+            let syllabus_code = resolve_syllabus(&path, &code);
+            Some(LibraryPaper {
+                path,
+                syllabus_code,
+                paper,
+            })
+        })
+        .collect()
+}
+
+/// Like `scan_library`, but only keeps papers of the given type.
+pub fn scan_library_by_type(root: &Path, layout: &Layout, paper_type: PaperType) -> Vec<LibraryPaper> {
+    scan_library(root, layout)
+        .into_iter()
+        .filter(|entry| entry.paper.paper_type == paper_type)
+        .collect()
+}
+
+fn resolve_syllabus(path: &Path, code: &str) -> SyllabusCode {
+    if let Some(known) = SYLLABUS_CODES.iter().find(|x| x.syllabus_code == code) {
+        return known.clone();
+    }
+    // Fall back to the subject folder name, "<Name> (<code>)".
+    let name = path
+        .ancestors()
+        .filter_map(|x| x.file_name()?.to_str())
+        .find(|x| x.ends_with(&format!("({})", code)))
+        .map(|x| x.trim_end_matches(&format!("({})", code)).trim().to_string())
+        .unwrap_or(code.to_string());
+    SyllabusCode::new(&name, "", code)
+}
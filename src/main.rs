@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
-use gce_scraper::{config_gen::{handle_generate, GenerationConfig, PaperGenerationConfig}, configuration::{PaperType, Season}, download::{handle_download, DownloadConfiguration}};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use gce_scraper::{archive::{ArchiveFormat, ArchiveOptions, ArchiveScope}, config_compose::{handle_show, ShowConfiguration}, config_export::{handle_export, ExportConfiguration, UrlListFormat}, config_format::ConfigFormat, config_gen::{handle_generate, GenerationConfig, PaperGenerationConfig}, config_migrate::{handle_migrate, MigrateConfiguration}, config_validate::{handle_validate, ValidateConfiguration}, config_wizard::{handle_wizard, WizardConfiguration}, dedup::{handle_dedup, DedupConfiguration}, configuration::{PaperType, Season}, defaults::{apply_defaults, collect_defaults, handle_defaults, load_user_defaults, unknown_keys, DefaultsConfiguration, UserDefaults}, download::{handle_download, DownloadConfiguration}, import::{handle_import, ImportConfiguration}, layout::Layout, metadata::{handle_metadata, MetadataAction, MetadataConfiguration}, pack::{handle_pack, PackConfiguration, PackScope}, search::{handle_index, handle_search, IndexConfiguration, SearchConfiguration}, split::{handle_split, SplitConfiguration}, thresholds::{handle_thresholds, ExportFormat, ThresholdConfiguration}, throttle::{Rate, Schedule, Window}, verify::{handle_verify, VerifyConfiguration}};
 use log::debug;
 
 
@@ -16,10 +16,33 @@ struct Args {
         long,
         value_name = "threads",
         default_value = "4",
+        value_parser = clap::value_parser!(u8).range(1..),
         long_help = "Number of threads to use for I/O operations."
     )]
     threads: u8,
 
+    #[arg(
+        long,
+        value_name = "template",
+        long_help = "Where papers go inside the library, e.g. \"{code}/{session}/{component}/{type}.pdf\". Overrides the configuration and the layout recorded in the library. Fields: {subject}, {code}, {year}, {yy}, {season}, {season_name}, {session}, {type}, {variant}, {component}, {filename}."
+    )]
+    layout: Option<Layout>,
+
+    #[arg(
+        long,
+        value_name = "rate",
+        long_help = "Bandwidth shared by all downloads, e.g. \"500K\", \"2MB\" or \"1MiB/s\", a lowercase b counts bits. Overrides the configuration's bandwidth."
+    )]
+    limit_rate: Option<Rate>,
+
+    #[arg(
+        long,
+        value_name = "window",
+        value_delimiter = ',',
+        long_help = "Local times of day downloads may run, e.g. \"18:00-07:00\". Outside them downloads pause until a window opens, papers already downloading pause between chunks. Overrides the configuration's windows."
+    )]
+    window: Vec<Window>,
+
     #[command(subcommand)]
     generate: Subs,
 }
@@ -30,6 +53,8 @@ enum Subs {
     GenerateConfig {
         #[arg(short, long, value_name = "output", default_value = "config.toml")]
         output: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the output file extension.")]
+        format: Option<ConfigFormat>,
         #[arg(short = 'p', long, value_name = "paper", value_delimiter=',', default_value="qp,ms,er")]
         papers: Vec<PaperType>,
         #[arg(short, long, value_name = "years", value_delimiter=',')]
@@ -37,13 +62,29 @@ enum Subs {
         #[arg(short, long, value_name = "subjects", value_delimiter=',')]
         subjects: Option<Vec<String>>,
         #[arg(long, value_name = "seasons", value_delimiter=',' , default_value = "winter,summer")]
-        seasons: Option<Vec<Season>>
+        seasons: Option<Vec<Season>>,
+        #[arg(
+            long,
+            long_help = "Merge missing and recent years into the existing configuration instead of replacing it. Paper types and sessions follow the existing configuration, hand edits are kept."
+        )]
+        update: bool,
+        #[arg(long, value_name = "years", default_value = "1", long_help = "Number of latest years fetched again with --update.")]
+        recent: u8,
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["years", "subjects", "update"],
+            long_help = "Walk through picking subjects, years, seasons and paper types in the terminal, preview the paper count, then write the configuration. --papers and --seasons are preselected."
+        )]
+        interactive: bool,
     },
 
     #[command(about = "Download the files specified in the configuration file.")]
     Download {
         #[arg(short, long, value_name = "config", default_value = "config.toml")]
         config: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the config file extension.")]
+        format: Option<ConfigFormat>,
         #[arg(
             short,
             long,
@@ -51,11 +92,298 @@ enum Subs {
             default_value = "Past Papers",
             long_help = "Name of the directory to store in/create."
         )]
-        output: PathBuf
+        output: PathBuf,
+        #[arg(long, long_help = "Write descriptive title, subject, author and keywords into each downloaded PDF.")]
+        metadata: bool,
+        #[arg(short, long, value_name = "profile", long_help = "Profile of the configuration to apply on top of its base.")]
+        profile: Option<String>,
+        #[arg(
+            long,
+            long_help = "Print one JSON object per line on stdout for every file started, progress, finished or failed, instead of drawing progress bars. Events name the file and the path it is saved to, the path is unique within a run."
+        )]
+        progress_json: bool,
+        #[arg(
+            long,
+            long_help = "List every paper's URL and destination, whether it already exists and conflicting destinations, without downloading anything."
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            long_help = "Make the output folder match the configuration: download missing papers and move papers downloaded earlier that are no longer selected into .gce-scraper/trash. Files the scraper didn't download are never touched."
+        )]
+        sync: bool,
+        #[arg(short, long, long_help = "Don't ask for confirmation before --sync moves files to the trash.")]
+        yes: bool,
+        #[arg(
+            long,
+            value_name = "format",
+            conflicts_with_all = ["sync", "metadata"],
+            long_help = "Write the papers straight into archives in the output folder instead of saving them as files. Papers keep the paths the layout gives them inside the archive."
+        )]
+        archive: Option<ArchiveFormat>,
+        #[arg(
+            long,
+            value_name = "scope",
+            default_value = "subject",
+            requires = "archive",
+            long_help = "Write one archive per subject, e.g. \"Mathematics (9709).zip\", or one for the whole run, \"papers.zip\"."
+        )]
+        archive_per: ArchiveScope,
+        #[arg(
+            long,
+            requires = "archive",
+            long_help = "Add to existing archives instead of replacing them, papers they already hold are not downloaded again."
+        )]
+        append: bool,
+    },
+
+    #[command(about = "Build a configuration from a list of paper codes or gceguide URLs, one per line.")]
+    Import {
+        #[arg(
+            short,
+            long,
+            value_name = "input",
+            long_help = "File listing papers such as 9709_s23_qp_12, \"9702 w22 ms 42\" or full URLs. Reads stdin when missing or -."
+        )]
+        input: Option<PathBuf>,
+        #[arg(short, long, value_name = "output", default_value = "config.toml")]
+        output: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the output file extension.")]
+        format: Option<ConfigFormat>,
+    },
+
+    #[command(about = "Extract grade boundaries from downloaded grade threshold papers.")]
+    Thresholds {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(short, long, value_name = "output", default_value = "thresholds.csv")]
+        output: PathBuf,
+        #[arg(short, long, value_name = "format", default_value = "csv")]
+        format: ExportFormat,
+    },
+
+    #[command(about = "Build a full-text search index over the downloaded papers.")]
+    Index {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(long, value_name = "index", long_help = "Index file location. Defaults to a file inside the input folder.")]
+        index: Option<PathBuf>,
+    },
+
+    #[command(about = "Search the text of the downloaded papers.")]
+    Search {
+        query: String,
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(long, value_name = "index", long_help = "Index file location. Defaults to a file inside the input folder.")]
+        index: Option<PathBuf>,
+        #[arg(short, long, value_name = "subjects", value_delimiter=',')]
+        subjects: Option<Vec<String>>,
+        #[arg(short, long, value_name = "years", value_delimiter=',')]
+        years: Option<Vec<String>>,
+        #[arg(long, value_name = "seasons", value_delimiter=',')]
+        seasons: Option<Vec<Season>>,
+        #[arg(short = 'p', long, value_name = "paper", value_delimiter=',')]
+        papers: Option<Vec<PaperType>>,
+        #[arg(short, long, value_name = "limit", default_value = "10")]
+        limit: usize,
+    },
+
+    #[command(about = "Split downloaded question papers into one PDF per question.")]
+    Split {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "output-folder",
+            default_value = "Questions",
+            long_help = "Name of the directory to store in/create."
+        )]
+        output: PathBuf,
+    },
+
+    #[command(about = "Combine question papers, inserts and mark schemes into study-pack PDFs.")]
+    Pack {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "output-folder",
+            default_value = "Study Packs",
+            long_help = "Name of the directory to store in/create."
+        )]
+        output: PathBuf,
+        #[arg(long, value_name = "scope", default_value = "paper")]
+        scope: PackScope,
+    },
+
+    #[command(about = "Write, restore or verify descriptive metadata in downloaded PDFs.")]
+    Metadata {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(long, value_name = "action", default_value = "write")]
+        action: MetadataAction,
+    },
+
+    #[command(about = "Store identical downloaded papers once, linking every copy to a content-addressed store.")]
+    Dedup {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(
+            long,
+            value_name = "store",
+            long_help = "Store folder, shared between libraries to deduplicate across them. Defaults to a folder inside the input folder. Papers are hardlinked to it, or symlinked when it is on another file system."
+        )]
+        store: Option<PathBuf>,
+        #[arg(long, long_help = "Only report the space deduplication would save.")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Check downloaded papers against the sizes and checksums recorded when they were downloaded.")]
+    Verify {
+        #[arg(
+            short,
+            long,
+            value_name = "input-folder",
+            default_value = "Past Papers",
+            long_help = "Directory containing the downloaded papers."
+        )]
+        input: PathBuf,
+        #[arg(long, long_help = "Download missing, truncated and corrupted papers again.")]
+        repair: bool,
+    },
+
+    #[command(about = "Inspect and maintain configuration files.")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigSubs,
     },
 }
+
+#[derive(Subcommand, Debug)]
+enum ConfigSubs {
+    #[command(about = "Rewrite a configuration file to the current schema version.")]
+    Migrate {
+        #[arg(short, long, value_name = "config", default_value = "config.toml")]
+        config: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "output",
+            long_help = "Where to write the migrated configuration. Defaults to overwriting the input."
+        )]
+        output: Option<PathBuf>,
+        #[arg(long, value_name = "format", long_help = "Format of the input configuration. Defaults to the file extension.")]
+        format: Option<ConfigFormat>,
+    },
+    #[command(about = "Print a configuration file, optionally with its includes and a profile merged in.")]
+    Show {
+        #[arg(short, long, value_name = "config", default_value = "config.toml")]
+        config: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the config file extension.")]
+        format: Option<ConfigFormat>,
+        #[arg(short, long, value_name = "profile", long_help = "Profile to apply on top of the base, implies --resolved.")]
+        profile: Option<String>,
+        #[arg(long, long_help = "Print the configuration after merging includes and the profile.")]
+        resolved: bool,
+    },
+    #[command(about = "Check a configuration file, reporting every problem with its line and column.")]
+    Validate {
+        #[arg(short, long, value_name = "config", default_value = "config.toml")]
+        config: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the config file extension.")]
+        format: Option<ConfigFormat>,
+    },
+    #[command(about = "List the URL and target path of every paper a configuration downloads, for external downloaders.")]
+    Export {
+        #[arg(short, long, value_name = "config", default_value = "config.toml")]
+        config: PathBuf,
+        #[arg(long, value_name = "format", long_help = "Configuration format. Defaults to the config file extension.")]
+        config_format: Option<ConfigFormat>,
+        #[arg(short, long, value_name = "profile", long_help = "Profile of the configuration to apply on top of its base.")]
+        profile: Option<String>,
+        #[arg(long, value_name = "format", default_value = "urls")]
+        format: UrlListFormat,
+        #[arg(
+            long,
+            value_name = "output-folder",
+            default_value = "Past Papers",
+            long_help = "Folder the target paths are built under, as with download --output."
+        )]
+        folder: PathBuf,
+        #[arg(short, long, value_name = "output", long_help = "File to write the listing to. Defaults to stdout.")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Show the default of every option and where it comes from: environment, user defaults file or built-in.")]
+    Defaults,
+}
+/// The layout of the library at `input`, exits when the recorded one can't be read.
+fn library_layout(requested: Option<&Layout>, input: &Path) -> Layout {
+    match Layout::for_library(requested, input) {
+        Ok(layout) => layout,
+        Err(e) => {
+            log::error!("Invalid library layout: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    let args = Args::parse();
+    // Environment and user file values become option defaults, typed options still take precedence.
+    let user_defaults = load_user_defaults();
+    let defaults = collect_defaults(
+        &Args::command(),
+        user_defaults.as_ref().unwrap_or(&UserDefaults::default()),
+    );
+    let matches = apply_defaults(Args::command(), &defaults).get_matches();
+    let args = match Args::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(e) => e.exit(),
+    };
     // Check verbosity flag and set RUST_LOG env variable
     match args.verbose.is_present() {
         true => {
@@ -73,15 +401,44 @@ fn main() {
         "Logger started with level: {}",
         std::env::var("RUST_LOG").unwrap()
     );
+    match &user_defaults {
+        Ok(user_defaults) => {
+            for key in unknown_keys(&Args::command(), &user_defaults.table) {
+                log::warn!("Unknown option {} in user defaults file.", key);
+            }
+        }
+        Err(e) => log::warn!("Ignoring user defaults file: {}", e),
+    }
+
+    // The configuration may fill in whatever the command line leaves out.
+    let schedule = Schedule {
+        bandwidth: args.limit_rate.clone(),
+        windows: args.window.clone(),
+    };
 
     // Handle subcommands
     match args.generate {
         Subs::Download {
             config,
+            format,
             output,
+            metadata,
+            profile,
+            progress_json,
+            dry_run,
+            sync,
+            yes,
+            archive,
+            archive_per,
+            append,
         } => {
             debug!("Selected Download subcommand.");
-            handle_download(match DownloadConfiguration::new(config, output, args.threads) {
+            let archive = archive.map(|format| ArchiveOptions {
+                format,
+                scope: archive_per,
+                append,
+            });
+            handle_download(match DownloadConfiguration::new(config, output, format, profile, args.layout, args.threads, metadata, progress_json, dry_run, sync, yes, archive, schedule) {
                 Ok(config) => config,
                 Err(e) => {
                     match e {
@@ -94,6 +451,12 @@ fn main() {
                         gce_scraper::download::DownloadError::ConfigParseError(e) => {
                             log::error!("Error parsing configuration file: {}", e);
                         }
+                        gce_scraper::download::DownloadError::ConfigInvalid(errors) => {
+                            log::error!("Configuration file has {} errors, nothing was downloaded.", errors);
+                        }
+                        gce_scraper::download::DownloadError::InvalidLayout(e) => {
+                            log::error!("Invalid library layout: {}", e);
+                        }
                     }
                     std::process::exit(1);
                 }
@@ -102,22 +465,197 @@ fn main() {
         }
         Subs::GenerateConfig {
             output,
+            format,
             papers,
             years,
             subjects,
             seasons,
+            update,
+            recent,
+            interactive,
         } => {
             debug!("Selected GenerateConfig subcommand.");
-            handle_generate(GenerationConfig::new(
-                output,
-                PaperGenerationConfig {
+            if interactive {
+                handle_wizard(WizardConfiguration {
+                    output,
+                    format,
                     papers,
-                    years,
-                    subjects,
-                    seasons,
-                },
-                args.threads,
-            ));
+                    seasons: seasons.unwrap_or_default(),
+                    threads: args.threads,
+                });
+            } else {
+                handle_generate(GenerationConfig::new(
+                    output,
+                    format,
+                    PaperGenerationConfig {
+                        papers,
+                        years,
+                        subjects,
+                        seasons,
+                    },
+                    args.threads,
+                    update,
+                    recent,
+                ));
+            }
+        }
+        Subs::Import {
+            input,
+            output,
+            format,
+        } => {
+            debug!("Selected Import subcommand.");
+            handle_import(ImportConfiguration {
+                input,
+                output,
+                format,
+            });
+        }
+        Subs::Thresholds {
+            input,
+            output,
+            format,
+        } => {
+            debug!("Selected Thresholds subcommand.");
+            handle_thresholds(ThresholdConfiguration {
+                layout: library_layout(args.layout.as_ref(), &input),
+                input_folder: input,
+                output,
+                format,
+            });
+        }
+        Subs::Index { input, index } => {
+            debug!("Selected Index subcommand.");
+            handle_index(IndexConfiguration {
+                layout: library_layout(args.layout.as_ref(), &input),
+                input_folder: input,
+                index,
+                threads: args.threads,
+            });
+        }
+        Subs::Search {
+            query,
+            input,
+            index,
+            subjects,
+            years,
+            seasons,
+            papers,
+            limit,
+        } => {
+            debug!("Selected Search subcommand.");
+            handle_search(SearchConfiguration {
+                input_folder: input,
+                index,
+                query,
+                subjects,
+                years,
+                seasons,
+                papers,
+                limit,
+            });
+        }
+        Subs::Split { input, output } => {
+            debug!("Selected Split subcommand.");
+            handle_split(SplitConfiguration {
+                layout: library_layout(args.layout.as_ref(), &input),
+                input_folder: input,
+                output_folder: output,
+            });
         }
+        Subs::Pack {
+            input,
+            output,
+            scope,
+        } => {
+            debug!("Selected Pack subcommand.");
+            handle_pack(PackConfiguration {
+                layout: library_layout(args.layout.as_ref(), &input),
+                input_folder: input,
+                output_folder: output,
+                scope,
+            });
+        }
+        Subs::Metadata { input, action } => {
+            debug!("Selected Metadata subcommand.");
+            handle_metadata(MetadataConfiguration {
+                layout: library_layout(args.layout.as_ref(), &input),
+                input_folder: input,
+                action,
+            });
+        }
+        Subs::Dedup { input, store, dry_run } => {
+            debug!("Selected Dedup subcommand.");
+            handle_dedup(DedupConfiguration {
+                input_folder: input,
+                store,
+                dry_run,
+            });
+        }
+        Subs::Verify { input, repair } => {
+            debug!("Selected Verify subcommand.");
+            handle_verify(VerifyConfiguration {
+                input_folder: input,
+                repair,
+                threads: args.threads,
+                schedule,
+            });
+        }
+        Subs::Config { command } => match command {
+            ConfigSubs::Migrate {
+                config,
+                output,
+                format,
+            } => {
+                debug!("Selected Config Migrate subcommand.");
+                handle_migrate(MigrateConfiguration {
+                    config,
+                    output,
+                    format,
+                });
+            }
+            ConfigSubs::Show {
+                config,
+                format,
+                profile,
+                resolved,
+            } => {
+                debug!("Selected Config Show subcommand.");
+                handle_show(ShowConfiguration {
+                    config,
+                    format,
+                    resolved: resolved || profile.is_some(),
+                    profile,
+                });
+            }
+            ConfigSubs::Validate { config, format } => {
+                debug!("Selected Config Validate subcommand.");
+                handle_validate(ValidateConfiguration { config, format });
+            }
+            ConfigSubs::Export {
+                config,
+                config_format,
+                profile,
+                format,
+                folder,
+                output,
+            } => {
+                debug!("Selected Config Export subcommand.");
+                handle_export(ExportConfiguration {
+                    config,
+                    config_format,
+                    profile,
+                    output_folder: folder,
+                    layout: args.layout,
+                    format,
+                    output,
+                    threads: args.threads,
+                });
+            }
+            ConfigSubs::Defaults => {
+                debug!("Selected Config Defaults subcommand.");
+                handle_defaults(DefaultsConfiguration { defaults });
+            }
+        },
     }
 }
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config_format::write_atomically, library::METADATA_DIR, pdf::sha256_hex};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Folder inside the metadata folder holding the files `download --sync` pruned, one folder per run.
+const TRASH_DIR: &str = "trash";
+
+/// A file the scraper downloaded into the library.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative to the library root, separated by `/`.
+    pub path: String,
+    pub syllabus_code: String,
+    /// Listing the paper came from, empty for files recorded before it was kept.
+    #[serde(default)]
+    pub access_slug: String,
+    /// Name of the paper on the website, e.g. "9709_s23_qp_12.pdf".
+    pub file: String,
+    /// Size in bytes as downloaded.
+    #[serde(default)]
+    pub size: u64,
+    /// Hex SHA-256 of the file as downloaded, empty for files recorded before checksums were kept.
+    #[serde(default)]
+    pub sha256: String,
+}
+
+/// Size and hex SHA-256 of a file.
+pub fn fingerprint(path: &Path) -> Result<(u64, String), std::io::Error> {
+    let bytes = std::fs::read(path)?;
+    Ok((bytes.len() as u64, sha256_hex(&bytes)))
+}
+
+/// Every file the scraper created in a library. Anything else belongs to the user and is never pruned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+/// `path` relative to `root` in the form the manifest stores, `None` when it lies outside.
+pub fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let parts = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|x| x.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+/// Joins a manifest path onto the library root.
+pub fn library_path(root: &Path, relative: &str) -> PathBuf {
+    relative.split('/').fold(root.to_path_buf(), |path, x| path.join(x))
+}
+
+impl Manifest {
+    fn file(root: &Path) -> PathBuf {
+        root.join(METADATA_DIR).join(MANIFEST_FILE)
+    }
+
+    /// Reads the manifest of a library, a library without one has no tracked files.
+    pub fn load(root: &Path) -> Result<Manifest, String> {
+        let path = Manifest::file(root);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let content = std::fs::read(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+        serde_json::from_slice(&content).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), std::io::Error> {
+        std::fs::create_dir_all(root.join(METADATA_DIR))?;
+        let content = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        write_atomically(&Manifest::file(root), &content)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&ManifestEntry> {
+        self.files.iter().find(|x| x.path == path)
+    }
+
+    /// Adds an entry, replacing the one recorded for the same path.
+    pub fn record(&mut self, entry: ManifestEntry) {
+        self.remove(&entry.path);
+        self.files.push(entry);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.files.retain(|x| x.path != path);
+    }
+
+    /// Records the current size and checksum of a tracked file after the scraper changed it on purpose.
+    /// Returns false when the file isn't tracked.
+    pub fn refresh(&mut self, root: &Path, path: &Path) -> Result<bool, std::io::Error> {
+        let entry = match relative_path(root, path).and_then(|x| self.files.iter_mut().find(|y| y.path == x)) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        (entry.size, entry.sha256) = fingerprint(path)?;
+        Ok(true)
+    }
+}
+
+/// A fresh trash folder for this run, e.g. `.gce-scraper/trash/1718000000`.
+pub fn trash_folder(root: &Path) -> PathBuf {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    root.join(METADATA_DIR).join(TRASH_DIR).join(now.to_string())
+}
+
+/// Moves a library file into `trash` under the same relative path, then removes the folders it leaves empty.
+pub fn move_to_trash(root: &Path, trash: &Path, relative: &str) -> Result<PathBuf, std::io::Error> {
+    let source = library_path(root, relative);
+    let target = library_path(trash, relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&source, &target)?;
+    for folder in source.ancestors().skip(1) {
+        // Fails on the first folder still holding something.
+        if folder == root || std::fs::remove_dir(folder).is_err() {
+            break;
+        }
+    }
+    Ok(target)
+}
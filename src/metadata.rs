@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{
+    configuration::{Paper, SyllabusCode},
+    layout::Layout,
+    library::scan_library,
+    manifest::Manifest,
+    pdf::{restore_original, verify_original, write_info, PdfError},
+};
+
+const AUTHOR: &str = "Cambridge Assessment International Education";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetadataAction {
+    /// Set descriptive metadata on every paper.
+    Write,
+    /// Undo the metadata update, restoring the original bytes.
+    Restore,
+    /// Check that the original bytes are still intact.
+    Verify,
+}
+
+#[derive(Debug)]
+pub struct MetadataConfiguration {
+    pub input_folder: PathBuf,
+    pub layout: Layout,
+    pub action: MetadataAction,
+}
+
+/// Document information entries describing a paper.
+pub fn paper_metadata(syllabus_code: &SyllabusCode, paper: &Paper) -> Vec<(&'static str, String)> {
+    let session = format!("{} {}", paper.season.session_name(), paper.year);
+    let mut keywords = vec![
+        syllabus_code.syllabus_code.clone(),
+        syllabus_code.name.clone(),
+        session,
+        format!("{}{}", paper.season, &paper.year[paper.year.len() - 2..]),
+        paper.paper_type.long_name().to_string(),
+    ];
+    if !paper.variant.is_empty() {
+        keywords.push(format!("Paper {}", paper.variant));
+        keywords.push(format!("Component {}", paper.component()));
+        keywords.push(format!("Variant {}", paper.variant_number()));
+    }
+    vec![
+        (
+            "Title",
+            format!("{} – {}", paper.title(syllabus_code), paper.paper_type.long_name()),
+        ),
+        (
+            "Subject",
+            format!(
+                "{} ({}) {}",
+                syllabus_code.name,
+                syllabus_code.syllabus_code,
+                paper.paper_type.long_name()
+            ),
+        ),
+        ("Author", AUTHOR.to_string()),
+        ("Keywords", keywords.join(", ")),
+    ]
+}
+
+/// Writes the descriptive metadata of `paper` into the PDF at `path`.
+pub fn apply_metadata(syllabus_code: &SyllabusCode, paper: &Paper, path: &Path) -> Result<(), PdfError> {
+    write_info(path, &paper_metadata(syllabus_code, paper))
+}
+
+pub fn handle_metadata(config: MetadataConfiguration) {
+    let entries = scan_library(&config.input_folder, &config.layout);
+    if entries.is_empty() {
+        error!("No papers found in {:?}", config.input_folder);
+        std::process::exit(1);
+    }
+
+    let mut failed = 0;
+    let mut changed = vec![];
+    for entry in &entries {
+        // Whether the file was rewritten.
+        let result = match config.action {
+            MetadataAction::Write => apply_metadata(&entry.syllabus_code, &entry.paper, &entry.path).map(|_| {
+                info!("Updated metadata of {:?}", entry.path);
+                true
+            }),
+            MetadataAction::Restore => restore_original(&entry.path).inspect(|restored| match restored {
+                true => info!("Restored {:?}", entry.path),
+                false => debug!("{:?} has no metadata update.", entry.path),
+            }),
+            MetadataAction::Verify => verify_original(&entry.path).map(|hash| {
+                match hash {
+                    Some(hash) => info!("{:?} matches original {}", entry.path, hash),
+                    None => debug!("{:?} has no metadata update.", entry.path),
+                }
+                false
+            }),
+        };
+        match result {
+            Ok(true) => changed.push(&entry.path),
+            Ok(false) => {}
+            Err(e) => {
+                error!("{:?}: {}", entry.path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    // Keep `verify` from mistaking the rewritten papers for corrupted ones.
+    if !changed.is_empty() {
+        match Manifest::load(&config.input_folder) {
+            Ok(mut manifest) => {
+                for path in changed {
+                    if let Err(e) = manifest.refresh(&config.input_folder, path) {
+                        warn!("Failed to update the checksum of {:?}: {}", path, e);
+                    }
+                }
+                if let Err(e) = manifest.save(&config.input_folder) {
+                    warn!("Failed to save the library manifest: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to read the library manifest: {}", e),
+        }
+    }
+    if failed > 0 {
+        error!("{} of {} papers failed.", failed, entries.len());
+        std::process::exit(1);
+    }
+}
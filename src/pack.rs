@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{
+    configuration::{PaperType, SyllabusCode},
+    layout::Layout,
+    library::{scan_library, LibraryPaper},
+    pdf::{merge_sections, save, MergeSection},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PackScope {
+    /// One pack per question paper.
+    Paper,
+    /// One pack per subject and year, with a contents page.
+    Year,
+    /// One pack per subject, with a contents page.
+    Subject,
+}
+
+#[derive(Debug)]
+pub struct PackConfiguration {
+    pub input_folder: PathBuf,
+    pub layout: Layout,
+    pub output_folder: PathBuf,
+    pub scope: PackScope,
+}
+
+/// Builds the section for a question paper: the paper, its insert if present, and its mark scheme.
+fn paper_section(qp: &LibraryPaper, library: &[LibraryPaper]) -> MergeSection {
+    let find = |paper_type: PaperType| {
+        library.iter().find(|x| {
+            x.syllabus_code.syllabus_code == qp.syllabus_code.syllabus_code
+                && x.paper.year == qp.paper.year
+                && x.paper.season == qp.paper.season
+                && x.paper.variant == qp.paper.variant
+                && x.paper.paper_type == paper_type
+        })
+    };
+    let mut parts = vec![(PaperType::QP.long_name().to_string(), qp.path.clone())];
+    for paper_type in [PaperType::IN, PaperType::MS] {
+        match find(paper_type.clone()) {
+            Some(found) => parts.push((paper_type.long_name().to_string(), found.path.clone())),
+            None => debug!(
+                "No {} found for {:?}",
+                paper_type.long_name(),
+                qp.path
+            ),
+        }
+    }
+    MergeSection {
+        title: qp.paper.title(&qp.syllabus_code),
+        parts,
+    }
+}
+
+fn subject_folder(config: &PackConfiguration, syllabus_code: &SyllabusCode) -> PathBuf {
+    config.output_folder.join(format!(
+        "{} ({})",
+        syllabus_code.name, syllabus_code.syllabus_code
+    ))
+}
+
+fn write_pack(sections: &[MergeSection], contents_title: Option<&str>, output: PathBuf) -> bool {
+    if let Some(parent) = output.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            error!("Failed to create folder {:?}: {}", parent, e);
+            return false;
+        }
+    }
+    let written = merge_sections(sections, contents_title)
+        .and_then(|mut document| save(&mut document, &output));
+    match written {
+        Ok(_) => {
+            info!("Saved pack to: {:?}", output);
+            true
+        }
+        Err(e) => {
+            error!("Failed to build pack {:?}: {}", output, e);
+            false
+        }
+    }
+}
+
+/// Papers go into packs by subject, then in the order they were sat.
+fn pack_order(qp: &LibraryPaper) -> (&str, &str, u8, &str) {
+    (
+        &qp.syllabus_code.syllabus_code,
+        &qp.paper.year,
+        qp.paper.season.calendar_order(),
+        &qp.paper.variant,
+    )
+}
+
+pub fn handle_pack(config: PackConfiguration) {
+    let library = scan_library(&config.input_folder, &config.layout);
+    let mut question_papers = library
+        .iter()
+        .filter(|x| x.paper.paper_type == PaperType::QP)
+        .collect::<Vec<_>>();
+    if question_papers.is_empty() {
+        error!("No question papers found in {:?}", config.input_folder);
+        std::process::exit(1);
+    }
+    question_papers.sort_by(|a, b| pack_order(a).cmp(&pack_order(b)));
+
+    let mut written = 0;
+    match config.scope {
+        PackScope::Paper => {
+            for qp in question_papers {
+                let file = qp
+                    .paper
+                    .get_ref_filename(&qp.syllabus_code)
+                    .replacen("_qp_", "_pack_", 1);
+                let output = subject_folder(&config, &qp.syllabus_code)
+                    .join(&qp.paper.year)
+                    .join(file);
+                written += write_pack(&[paper_section(qp, &library)], None, output) as usize;
+            }
+        }
+        PackScope::Year | PackScope::Subject => {
+            let mut groups: BTreeMap<(String, String), Vec<&LibraryPaper>> = BTreeMap::new();
+            for qp in question_papers {
+                let year = match config.scope {
+                    PackScope::Year => qp.paper.year.clone(),
+                    _ => String::new(),
+                };
+                groups
+                    .entry((qp.syllabus_code.syllabus_code.clone(), year))
+                    .or_default()
+                    .push(qp);
+            }
+            for ((code, year), papers) in groups {
+                let syllabus_code = &papers[0].syllabus_code;
+                let sections = papers
+                    .iter()
+                    .map(|qp| paper_section(qp, &library))
+                    .collect::<Vec<_>>();
+                let (title, output) = if year.is_empty() {
+                    (
+                        format!("{} {}", syllabus_code.name, code),
+                        subject_folder(&config, syllabus_code).join(format!("{}_pack.pdf", code)),
+                    )
+                } else {
+                    (
+                        format!("{} {} – {}", syllabus_code.name, code, year),
+                        subject_folder(&config, syllabus_code)
+                            .join(&year)
+                            .join(format!("{}_{}_pack.pdf", code, year)),
+                    )
+                };
+                written += write_pack(&sections, Some(&title), output) as usize;
+            }
+        }
+    }
+    info!("Wrote {} packs to {:?}", written, config.output_folder);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{Paper, Season};
+
+    #[test]
+    fn papers_follow_the_calendar() {
+        let syllabus_code = SyllabusCode::new("Mathematics", "mathematics-(9709)", "9709");
+        let mut papers = [
+            ("2023", Season::Winter),
+            ("2023", Season::March),
+            ("2022", Season::Winter),
+            ("2023", Season::Summer),
+        ]
+        .map(|(year, season)| LibraryPaper {
+            path: PathBuf::new(),
+            syllabus_code: syllabus_code.clone(),
+            paper: Paper::new(year, season, PaperType::QP, "12"),
+        });
+        papers.sort_by(|a, b| pack_order(a).cmp(&pack_order(b)));
+        let order = papers.iter().map(|x| (x.paper.year.as_str(), x.paper.season.clone())).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                ("2022", Season::Winter),
+                ("2023", Season::March),
+                ("2023", Season::Summer),
+                ("2023", Season::Winter),
+            ]
+        );
+    }
+}
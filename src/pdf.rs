@@ -0,0 +1,442 @@
+use std::path::{Path, PathBuf};
+
+use lopdf::{
+    content::{Content, Operation},
+    dictionary, text_string, Bookmark, Dictionary, Document, IncrementalDocument, Object,
+    ObjectId, Stream,
+};
+use sha2::{Digest, Sha256};
+use pdf_extract::{MediaBox, OutputDev, OutputError, Transform};
+
+#[derive(Debug)]
+pub enum PdfError {
+    ExtractError(String),
+    ExtractPanicked,
+    LoadError(lopdf::Error),
+    SaveError(std::io::Error),
+    ReadError(std::io::Error),
+    Encrypted,
+    OriginalMismatch,
+}
+
+impl std::fmt::Display for PdfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PdfError::ExtractError(e) => write!(f, "failed to extract text: {}", e),
+            PdfError::ExtractPanicked => write!(f, "text extraction panicked"),
+            PdfError::LoadError(e) => write!(f, "failed to load PDF: {}", e),
+            PdfError::SaveError(e) => write!(f, "failed to save PDF: {}", e),
+            PdfError::ReadError(e) => write!(f, "failed to read PDF: {}", e),
+            PdfError::Encrypted => write!(f, "PDF is encrypted"),
+            PdfError::OriginalMismatch => {
+                write!(f, "recorded original no longer matches the file contents")
+            }
+        }
+    }
+}
+
+/// Extracts the plain text of every page of the PDF at `path`.
+pub fn extract_pages(path: &Path) -> Result<Vec<String>, PdfError> {
+    // pdf-extract panics on some malformed fonts, don't take the whole run down with it.
+    let path = path.to_path_buf();
+    let result = std::panic::catch_unwind(move || pdf_extract::extract_text_by_pages(&path));
+    match result {
+        Ok(Ok(pages)) => Ok(pages),
+        Ok(Err(e)) => Err(PdfError::ExtractError(e.to_string())),
+        Err(_) => Err(PdfError::ExtractPanicked),
+    }
+}
+
+/// Extracts the plain text of the whole PDF at `path`.
+pub fn extract_text(path: &Path) -> Result<String, PdfError> {
+    extract_pages(path).map(|pages| pages.join("\n"))
+}
+
+/// A word placed on a page, with its position in PDF user space.
+#[derive(Debug, Clone)]
+pub struct PlacedWord {
+    pub text: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// The words of a page along with the page's media box.
+#[derive(Debug, Clone)]
+pub struct PlacedPage {
+    pub number: u32,
+    pub left: f64,
+    pub width: f64,
+    pub words: Vec<PlacedWord>,
+}
+
+#[derive(Default)]
+struct WordCollector {
+    pages: Vec<PlacedPage>,
+    current: Option<PlacedWord>,
+}
+
+impl WordCollector {
+    fn flush(&mut self) {
+        if let (Some(word), Some(page)) = (self.current.take(), self.pages.last_mut()) {
+            if !word.text.trim().is_empty() {
+                page.words.push(word);
+            }
+        }
+    }
+}
+
+impl OutputDev for WordCollector {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.pages.push(PlacedPage {
+            number: page_num,
+            left: media_box.llx,
+            width: media_box.urx - media_box.llx,
+            words: vec![],
+        });
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        self.flush();
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        _width: f64,
+        _spacing: f64,
+        _font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        if char.trim().is_empty() {
+            self.flush();
+            return Ok(());
+        }
+        match self.current.as_mut() {
+            Some(word) => word.text.push_str(char),
+            None => {
+                self.current = Some(PlacedWord {
+                    text: char.to_string(),
+                    x: trm.m31,
+                    y: trm.m32,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.flush();
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.flush();
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.flush();
+        Ok(())
+    }
+}
+
+/// Extracts every word of the PDF at `path` together with where it sits on the page.
+pub fn extract_placed_words(path: &Path) -> Result<Vec<PlacedPage>, PdfError> {
+    let document = load(path)?;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+        let mut collector = WordCollector::default();
+        pdf_extract::output_doc(&document, &mut collector).map(|_| collector.pages)
+    }));
+    match result {
+        Ok(Ok(pages)) => Ok(pages),
+        Ok(Err(e)) => Err(PdfError::ExtractError(e.to_string())),
+        Err(_) => Err(PdfError::ExtractPanicked),
+    }
+}
+
+pub fn load(path: &Path) -> Result<Document, PdfError> {
+    Document::load(path).map_err(PdfError::LoadError)
+}
+
+/// Writes a copy of `document` holding only the given 1-based `pages`.
+pub fn save_pages(document: &Document, pages: &[u32], output: &Path) -> Result<(), PdfError> {
+    let mut document = document.clone();
+    let remove = document
+        .get_pages()
+        .into_keys()
+        .filter(|x| !pages.contains(x))
+        .collect::<Vec<_>>();
+    document.delete_pages(&remove);
+    document.prune_objects();
+    save(&mut document, output)
+}
+
+/// Attributes a page may inherit from its parent `Pages` nodes.
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+const CONTENTS_LINES_PER_PAGE: usize = 45;
+
+/// A bookmarked group of documents in a merged PDF, e.g. one paper with its mark scheme.
+#[derive(Debug, Clone)]
+pub struct MergeSection {
+    pub title: String,
+    pub parts: Vec<(String, PathBuf)>,
+}
+
+/// Copies inherited attributes onto the page itself so it survives being moved to a new page tree.
+fn flatten_page(document: &Document, page_id: ObjectId) -> Option<Dictionary> {
+    let mut page = document.get_dictionary(page_id).ok()?.clone();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(parent_id) = parent {
+        let node = match document.get_dictionary(parent_id) {
+            Ok(node) => node,
+            Err(_) => break,
+        };
+        for key in INHERITABLE {
+            if !page.has(key) {
+                if let Ok(value) = node.get(key) {
+                    page.set(key, value.clone());
+                }
+            }
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    Some(page)
+}
+
+fn contents_pages(
+    merged: &mut Document,
+    pages_id: ObjectId,
+    title: &str,
+    entries: &[(String, usize)],
+) -> Vec<ObjectId> {
+    let font_id = merged.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let mut lines = vec![(title.to_string(), None)];
+    lines.extend(entries.iter().map(|(x, page)| (x.clone(), Some(*page))));
+
+    lines
+        .chunks(CONTENTS_LINES_PER_PAGE)
+        .map(|chunk| {
+            let mut operations = vec![Operation::new("BT", vec![])];
+            for (i, (text, page)) in chunk.iter().enumerate() {
+                // The standard fonts only cover Latin-1, so dashes are flattened.
+                let text = text.replace(['–', '—'], "-");
+                let y = 790 - (i as i64) * 16;
+                operations.push(Operation::new("Tf", vec!["F1".into(), 11.into()]));
+                operations.push(Operation::new("Tm", vec![1.into(), 0.into(), 0.into(), 1.into(), 50.into(), y.into()]));
+                operations.push(Operation::new("Tj", vec![Object::string_literal(text)]));
+                if let Some(page) = page {
+                    operations.push(Operation::new("Tm", vec![1.into(), 0.into(), 0.into(), 1.into(), 520.into(), y.into()]));
+                    operations.push(Operation::new("Tj", vec![Object::string_literal(page.to_string())]));
+                }
+            }
+            operations.push(Operation::new("ET", vec![]));
+            let content = Content { operations }.encode().unwrap_or_default();
+            let content_id = merged.add_object(Stream::new(dictionary! {}, content));
+            merged.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            })
+        })
+        .collect()
+}
+
+/// Merges the parts of every section into one document, with a bookmark per section and part.
+/// When `contents_title` is set, a contents page listing each section's first page is prepended.
+pub fn merge_sections(
+    sections: &[MergeSection],
+    contents_title: Option<&str>,
+) -> Result<Document, PdfError> {
+    let mut merged = Document::with_version("1.5");
+    let pages_id = merged.new_object_id();
+
+    // Load everything first, the contents page needs to know every page number.
+    let mut loaded = vec![];
+    for section in sections {
+        let mut parts = vec![];
+        for (title, path) in &section.parts {
+            let mut document = load(path)?;
+            document.renumber_objects_with(merged.max_id + 1);
+            merged.max_id = document.max_id;
+            let page_ids = document.get_pages().into_values().collect::<Vec<_>>();
+            parts.push((title.clone(), document, page_ids));
+        }
+        loaded.push((section.title.clone(), parts));
+    }
+
+    let mut kids = vec![];
+    if let Some(contents_title) = contents_title {
+        let contents_count = (sections.len() + 1).div_ceil(CONTENTS_LINES_PER_PAGE);
+        let mut page = contents_count + 1;
+        let mut entries = vec![];
+        for (title, parts) in &loaded {
+            entries.push((title.clone(), page));
+            page += parts.iter().map(|x| x.2.len()).sum::<usize>();
+        }
+        kids.extend(contents_pages(&mut merged, pages_id, contents_title, &entries));
+    }
+
+    for (title, parts) in loaded {
+        let first_page = parts.iter().find_map(|x| x.2.first().copied());
+        let first_page = match first_page {
+            Some(page) => page,
+            None => continue,
+        };
+        let section = merged.add_bookmark(Bookmark::new(title, [0.0, 0.0, 0.0], 2, first_page), None);
+        for (title, document, page_ids) in parts {
+            if let Some(first) = page_ids.first() {
+                merged.add_bookmark(Bookmark::new(title, [0.0, 0.0, 0.0], 0, *first), Some(section));
+            }
+            for page_id in &page_ids {
+                if let Some(mut page) = flatten_page(&document, *page_id) {
+                    page.set("Parent", pages_id);
+                    merged.objects.insert(*page_id, Object::Dictionary(page));
+                    kids.push(*page_id);
+                }
+            }
+            for (id, object) in document.objects {
+                let skip = matches!(
+                    object.type_name().unwrap_or(b""),
+                    b"Catalog" | b"Pages" | b"Page" | b"Outlines" | b"Outline"
+                );
+                if !skip {
+                    merged.objects.insert(id, object);
+                }
+            }
+        }
+    }
+
+    merged.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as u32,
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+        }),
+    );
+    let catalog_id = merged.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+        "PageMode" => "UseOutlines",
+    });
+    merged.trailer.set("Root", catalog_id);
+
+    if let Some(outline_id) = merged.build_outline() {
+        if let Ok(Object::Dictionary(catalog)) = merged.get_object_mut(catalog_id) {
+            catalog.set("Outlines", Object::Reference(outline_id));
+        }
+    }
+    Ok(merged)
+}
+
+pub fn save(document: &mut Document, output: &Path) -> Result<(), PdfError> {
+    document.compress();
+    document
+        .save(output)
+        .map(|_| ())
+        .map_err(PdfError::SaveError)
+}
+
+/// Info dictionary keys recording the length and hash of the bytes before our metadata update.
+const ORIGINAL_LENGTH_KEY: &str = "GCEOriginalLength";
+const ORIGINAL_HASH_KEY: &str = "GCEOriginalSHA256";
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn info_dictionary(document: &Document) -> Option<Dictionary> {
+    match document.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => document.get_dictionary(*id).ok().cloned(),
+        Object::Dictionary(info) => Some(info.clone()),
+        _ => None,
+    }
+}
+
+/// Finds the original bytes of a file updated by `write_info`.
+/// Returns `None` when the file was never updated.
+fn recorded_original(bytes: &[u8]) -> Result<Option<&[u8]>, PdfError> {
+    let document = Document::load_mem(bytes).map_err(PdfError::LoadError)?;
+    let info = match info_dictionary(&document) {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    let length = match info.get(ORIGINAL_LENGTH_KEY.as_bytes()).and_then(Object::as_i64) {
+        Ok(length) => length as usize,
+        Err(_) => return Ok(None),
+    };
+    let hash = info
+        .get(ORIGINAL_HASH_KEY.as_bytes())
+        .and_then(Object::as_str)
+        .map(|x| String::from_utf8_lossy(x).to_string())
+        .unwrap_or_default();
+    match bytes.get(..length) {
+        Some(original) if sha256_hex(original) == hash => Ok(Some(original)),
+        _ => Err(PdfError::OriginalMismatch),
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), PdfError> {
+    let temporary = path.with_extension("pdf.tmp");
+    std::fs::write(&temporary, bytes)
+        .and_then(|_| std::fs::rename(&temporary, path))
+        .map_err(PdfError::SaveError)
+}
+
+/// Sets document information entries through an incremental update, so the original
+/// bytes stay untouched at the start of the file and can be restored with `restore_original`.
+pub fn write_info(path: &Path, entries: &[(&str, String)]) -> Result<(), PdfError> {
+    let bytes = std::fs::read(path).map_err(PdfError::ReadError)?;
+    let original = recorded_original(&bytes)?.unwrap_or(&bytes).to_vec();
+    let previous = Document::load_mem(&original).map_err(PdfError::LoadError)?;
+    if previous.is_encrypted() {
+        return Err(PdfError::Encrypted);
+    }
+
+    let mut info = info_dictionary(&previous).unwrap_or_default();
+    for (key, value) in entries {
+        info.set(*key, text_string(value));
+    }
+    info.set(ORIGINAL_LENGTH_KEY, Object::Integer(original.len() as i64));
+    info.set(ORIGINAL_HASH_KEY, Object::string_literal(sha256_hex(&original)));
+
+    let mut incremental = IncrementalDocument::create_from(original, previous);
+    let info_id = incremental.new_document.add_object(info);
+    incremental.new_document.trailer.set("Info", info_id);
+
+    let mut updated = vec![];
+    incremental
+        .save_to(&mut updated)
+        .map_err(|e| PdfError::SaveError(std::io::Error::other(e.to_string())))?;
+    write_atomic(path, &updated)
+}
+
+/// Strips a metadata update made by `write_info`. Returns whether anything was removed.
+pub fn restore_original(path: &Path) -> Result<bool, PdfError> {
+    let bytes = std::fs::read(path).map_err(PdfError::ReadError)?;
+    match recorded_original(&bytes)? {
+        Some(original) => write_atomic(path, original).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Checks that a file updated by `write_info` still holds its original bytes.
+/// Returns `None` when the file was never updated.
+pub fn verify_original(path: &Path) -> Result<Option<String>, PdfError> {
+    let bytes = std::fs::read(path).map_err(PdfError::ReadError)?;
+    Ok(recorded_original(&bytes)?.map(sha256_hex))
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write},
+    sync::Mutex,
+    time::Instant,
+};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+use crate::download::DownloadJob;
+
+/// Progress events are emitted at most once per this many bytes of a file.
+const EVENT_INTERVAL: u64 = 256 * 1024;
+
+/// One line of the `--progress-json` stream.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Started {
+        syllabus_code: String,
+        file: String,
+        path: String,
+    },
+    Progress {
+        file: String,
+        path: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    Finished {
+        file: String,
+        path: String,
+        bytes: u64,
+        elapsed_ms: u128,
+    },
+    Failed {
+        file: String,
+        path: String,
+        error: String,
+    },
+}
+
+fn file_name(job: &DownloadJob) -> String {
+    job.paper.get_ref_filename(&job.syllabus_code)
+}
+
+/// Identifies a job in events, file names repeat across subjects sharing a code but paths are unique.
+fn path(job: &DownloadJob) -> String {
+    job.path.display().to_string()
+}
+
+/// The bar of a subject and the bytes downloaded for it.
+struct SubjectBar {
+    bar: ProgressBar,
+    bytes: Mutex<u64>,
+}
+
+/// Live bars: one for the whole run and one per subject.
+pub struct Bars {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    subjects: HashMap<String, SubjectBar>,
+    bytes: Mutex<u64>,
+    started: Instant,
+}
+
+/// Reports download progress as terminal bars, a JSON event stream or not at all.
+pub enum Progress {
+    Bars(Bars),
+    /// Bytes last reported and start time of each running job, by path.
+    Json(Mutex<HashMap<String, (u64, Instant)>>),
+    /// Not a terminal, the log lines are all the feedback.
+    Off,
+}
+
+impl Progress {
+    /// JSON when asked for, bars on an interactive terminal, otherwise nothing.
+    pub fn new(json: bool, jobs: &[DownloadJob]) -> Progress {
+        if json {
+            return Progress::Json(Mutex::new(HashMap::new()));
+        }
+        if !std::io::stderr().is_terminal() {
+            return Progress::Off;
+        }
+
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(jobs.len() as u64));
+        overall.set_style(
+            ProgressStyle::with_template("{prefix:>24} [{bar:30.cyan/blue}] {pos}/{len} {msg} ETA {eta}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        overall.set_prefix("All subjects");
+        let style = ProgressStyle::with_template("{prefix:>24} [{bar:30}] {pos}/{len} {msg} ETA {eta}")
+            .unwrap()
+            .progress_chars("=> ");
+        let mut subjects: HashMap<String, SubjectBar> = HashMap::new();
+        for job in jobs {
+            subjects
+                .entry(job.syllabus_code.access_slug.clone())
+                .or_insert_with(|| {
+                    let bar = multi.add(ProgressBar::new(0));
+                    bar.set_style(style.clone());
+                    bar.set_prefix(format!("{} ({})", job.syllabus_code.name, job.syllabus_code.syllabus_code));
+                    SubjectBar {
+                        bar,
+                        bytes: Mutex::new(0),
+                    }
+                })
+                .bar
+                .inc_length(1);
+        }
+        Progress::Bars(Bars {
+            multi,
+            overall,
+            subjects,
+            bytes: Mutex::new(0),
+            started: Instant::now(),
+        })
+    }
+
+    /// Whether log lines should be held back so they don't tear the bars.
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, Progress::Bars(_))
+    }
+
+    fn emit(event: ProgressEvent) {
+        if let Ok(line) = serde_json::to_string(&event) {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", line);
+            let _ = stdout.flush();
+        }
+    }
+
+    pub fn started(&self, job: &DownloadJob) {
+        if let Progress::Json(files) = self {
+            files.lock().unwrap().insert(path(job), (0, Instant::now()));
+            Progress::emit(ProgressEvent::Started {
+                syllabus_code: job.syllabus_code.syllabus_code.clone(),
+                file: file_name(job),
+                path: path(job),
+            });
+        }
+    }
+
+    /// `received` bytes just arrived, `downloaded` counts every byte of this file so far.
+    pub fn advanced(&self, job: &DownloadJob, received: u64, downloaded: u64, total: Option<u64>) {
+        match self {
+            Progress::Bars(bars) => {
+                *bars.bytes.lock().unwrap() += received;
+                if let Some(subject) = bars.subjects.get(&job.syllabus_code.access_slug) {
+                    let mut bytes = subject.bytes.lock().unwrap();
+                    *bytes += received;
+                    subject.bar.set_message(bars.transferred(*bytes));
+                }
+                bars.update_message();
+            }
+            Progress::Json(files) => {
+                let mut files = files.lock().unwrap();
+                let last = match files.get_mut(&path(job)) {
+                    Some((last, _)) => last,
+                    None => return,
+                };
+                if downloaded - *last >= EVENT_INTERVAL || Some(downloaded) == total {
+                    *last = downloaded;
+                    drop(files);
+                    Progress::emit(ProgressEvent::Progress {
+                        file: file_name(job),
+                        path: path(job),
+                        downloaded,
+                        total,
+                    });
+                }
+            }
+            Progress::Off => {}
+        }
+    }
+
+    pub fn finished(&self, job: &DownloadJob, bytes: u64) {
+        match self {
+            Progress::Bars(bars) => {
+                bars.overall.inc(1);
+                if let Some(subject) = bars.subjects.get(&job.syllabus_code.access_slug) {
+                    subject.bar.inc(1);
+                }
+                bars.update_message();
+            }
+            Progress::Json(files) => {
+                let started = files.lock().unwrap().remove(&path(job));
+                Progress::emit(ProgressEvent::Finished {
+                    file: file_name(job),
+                    path: path(job),
+                    bytes,
+                    elapsed_ms: started.map(|(_, x)| x.elapsed().as_millis()).unwrap_or(0),
+                });
+            }
+            Progress::Off => {}
+        }
+    }
+
+    pub fn failed(&self, job: &DownloadJob, error: String) {
+        match self {
+            Progress::Bars(bars) => {
+                bars.overall.inc(1);
+                if let Some(subject) = bars.subjects.get(&job.syllabus_code.access_slug) {
+                    subject.bar.inc(1);
+                }
+                let _ = bars.multi.println(format!("Failed {}: {}", file_name(job), error));
+            }
+            Progress::Json(files) => {
+                files.lock().unwrap().remove(&path(job));
+                Progress::emit(ProgressEvent::Failed {
+                    file: file_name(job),
+                    path: path(job),
+                    error,
+                });
+            }
+            Progress::Off => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Progress::Bars(bars) = self {
+            bars.update_message();
+            bars.overall.finish();
+            for subject in bars.subjects.values() {
+                subject.bar.set_message(bars.transferred(*subject.bytes.lock().unwrap()));
+                subject.bar.finish();
+            }
+        }
+    }
+}
+
+impl Bars {
+    /// Bytes so far and the average rate since the run started, e.g. "3.2 MiB at 410 KiB/s".
+    fn transferred(&self, bytes: u64) -> String {
+        let rate = bytes as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        format!("{} at {}/s", HumanBytes(bytes), HumanBytes(rate as u64))
+    }
+
+    fn update_message(&self) {
+        self.overall.set_message(self.transferred(*self.bytes.lock().unwrap()));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    config_gen::{fetch_papers, YearSelection},
+    configuration::{Configuration, Season, SelectionRule, SyllabusCode, YearConfiguration},
+};
+
+/// Resolves one rule against the live listing, with the number of listings that could not be fetched.
+async fn resolve_rule(rule: &SelectionRule, config: &Configuration, threads: u8) -> (Vec<YearConfiguration>, usize) {
+    let syllabus_codes = rule
+        .subjects
+        .iter()
+        .filter_map(|x| {
+            let code = SyllabusCode::find(x);
+            if code.is_none() {
+                error!("Invalid subject code in rule: {}", x);
+                std::process::exit(1);
+            }
+            code
+        })
+        .collect::<Vec<_>>();
+    let seasons = rule
+        .seasons
+        .clone()
+        .unwrap_or(vec![Season::March, Season::Summer, Season::Winter]);
+    // Without types of its own a rule follows the subject overrides, so fetch every type any of them selects.
+    let papers = rule.papers.clone().unwrap_or_else(|| config.selected_paper_types());
+
+    let (mut subjects, failed) = fetch_papers(
+        syllabus_codes,
+        YearSelection::Range(rule.years.clone().unwrap_or_default()),
+        seasons.clone(),
+        papers,
+        threads,
+    )
+    .await;
+    for subject in subjects.iter_mut() {
+        let selected = match &rule.papers {
+            Some(papers) => papers,
+            None => config
+                .subjects
+                .iter()
+                .find(|x| x.syllabus_code.access_slug == subject.syllabus_code.access_slug)
+                .and_then(|x| x.paper_types.as_ref())
+                .unwrap_or(&config.papers),
+        };
+        subject.papers.retain(|paper| {
+            (selected.is_empty() || selected.contains(&paper.paper_type))
+                && seasons.contains(&paper.season)
+                // Examiner reports and grade thresholds cover every component, keep them.
+                && rule.components.as_ref().is_none_or(|x| {
+                    paper.variant.is_empty() || x.iter().any(|y| y == paper.component())
+                })
+        });
+    }
+    (subjects, failed)
+}
+
+/// A selection missing the papers of listings that could not be fetched.
+#[derive(Debug)]
+pub struct IncompleteSelection {
+    /// Everything that could be resolved.
+    pub resolved: Box<Configuration>,
+    /// Listings that failed.
+    pub failed: usize,
+}
+
+/// Returns a copy of `config` with every selection rule expanded into explicit papers.
+/// Papers listed explicitly are kept, duplicates are dropped.
+/// Fails when a listing couldn't be fetched, the partial selection is in the error.
+pub fn resolve_rules(
+    config: &Configuration,
+    rt: &tokio::runtime::Runtime,
+    threads: u8,
+) -> Result<Configuration, IncompleteSelection> {
+    let mut resolved = config.clone();
+    if config.rules.is_empty() {
+        return Ok(resolved);
+    }
+    info!("Resolving {} selection rules.", config.rules.len());
+
+    let mut failed = 0;
+    for rule in &config.rules {
+        let (subjects, rule_failed) = rt.block_on(resolve_rule(rule, config, threads));
+        failed += rule_failed;
+        for subject in subjects {
+            match resolved
+                .subjects
+                .iter_mut()
+                .find(|x| x.syllabus_code.access_slug == subject.syllabus_code.access_slug)
+            {
+                Some(existing) => {
+                    for paper in subject.papers {
+                        if !existing.papers.contains(&paper) {
+                            existing.papers.push(paper);
+                        }
+                    }
+                }
+                None => resolved.subjects.push(subject),
+            }
+        }
+    }
+    resolved.rules.clear();
+    info!(
+        "Selection resolved to {} papers.",
+        resolved.subjects.iter().map(|x| x.papers.len()).sum::<usize>()
+    );
+    match failed {
+        0 => Ok(resolved),
+        failed => Err(IncompleteSelection {
+            resolved: Box::new(resolved),
+            failed,
+        }),
+    }
+}
@@ -2,9 +2,11 @@ use std::{path::PathBuf, str::FromStr, sync::LazyLock};
 
 use kuchikiki::traits::TendrilSink;
 
-use crate::configuration::{Paper, PaperType, Season, SyllabusCode};
+use crate::{
+    configuration::{Paper, PaperType, Season, SyllabusCode},
+    throttle,
+};
 
-const BASE_URL: &str = "https://papers.gceguide.cc/a-levels/";
 static REQWEST_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
 
 #[derive(Debug)]
@@ -23,15 +25,16 @@ pub struct PaperRequest {
 }
 
 
-pub async fn get_all_papers(request: &PaperRequest) -> Vec<Paper> {
+/// Lists the papers of a syllabus year. Fails when the listing can't be fetched, an empty list means there are none.
+pub async fn get_all_papers(request: &PaperRequest) -> Result<Vec<Paper>, RequestError> {
     let url = format!(
         "{}{}/{}",
-        BASE_URL, request.syllabus.access_slug, request.year
+        request.syllabus.board().base_url, request.syllabus.access_slug, request.year
     );
     info!("Requesting papers from: {}", url);
 
     let client = REQWEST_CLIENT.get(url).send().await;
-    let res = match client {
+    let res = match client.and_then(|x| x.error_for_status()) {
         Ok(response) => {
             let body = response.text().await;
             match body {
@@ -41,12 +44,13 @@ pub async fn get_all_papers(request: &PaperRequest) -> Vec<Paper> {
         }
         Err(e) => Err(RequestError::ReqwestError(e)),
     };
-    if let Err(e) = res {
-        error!("Error: {:?}", e);
-        return vec![];
-    }
-
-    let body = res.unwrap();
+    let body = match res {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Error: {:?}", e);
+            return Err(e);
+        }
+    };
     let document = kuchikiki::parse_html().one(body);
 
     let paper_nodes = document.document_node.select(".name");
@@ -54,7 +58,7 @@ pub async fn get_all_papers(request: &PaperRequest) -> Vec<Paper> {
         Ok(paper_nodes) => paper_nodes
             .map(|node| node.as_node().text_contents())
             .collect(),
-        Err(_) => return vec![],
+        Err(_) => return Err(RequestError::NotFound("No paper elements found.")),
     };
 
     let mut papers = vec![];
@@ -71,11 +75,11 @@ pub async fn get_all_papers(request: &PaperRequest) -> Vec<Paper> {
     }
 
     
-    papers
+    Ok(papers)
 }
 
 pub async fn get_all_years(syllabus: &SyllabusCode) -> Result<Vec<String>, RequestError> {
-    let url = format!("{}{}", BASE_URL, syllabus.access_slug);
+    let url = format!("{}{}", syllabus.board().base_url, syllabus.access_slug);
 
     info!("Requesting years from: {}", url);
     // need to run blocking code here
@@ -113,33 +117,133 @@ pub async fn get_all_years(syllabus: &SyllabusCode) -> Result<Vec<String>, Reque
     }
 }
 
-pub async fn save_paper(syllabus: &SyllabusCode, paper: &Paper, output_file: &PathBuf) {
-    let url = format!(
+/// Where a paper is published.
+pub fn paper_url(syllabus: &SyllabusCode, paper: &Paper) -> String {
+    format!(
         "{}{}/{}/{}",
-        BASE_URL, syllabus.access_slug, paper.year, paper.get_ref_filename(syllabus)
-    );
+        syllabus.board().base_url, syllabus.access_slug, paper.year, paper.get_ref_filename(syllabus)
+    )
+}
+
+pub async fn save_paper(syllabus: &SyllabusCode, paper: &Paper, output_file: &PathBuf) -> Result<(), RequestError> {
+    save_paper_with_progress(syllabus, paper, output_file, |_, _| {})
+        .await
+        .map(|_| ())
+}
+
+/// Downloads a paper into memory, calling `on_progress` with the bytes received so far and the expected
+/// total after every chunk.
+pub async fn fetch_paper_with_progress<F: FnMut(u64, Option<u64>)>(
+    syllabus: &SyllabusCode,
+    paper: &Paper,
+    on_progress: F,
+) -> Result<Vec<u8>, RequestError> {
+    fetch_pdf(&paper_url(syllabus, paper), on_progress).await
+}
+
+/// Downloads the PDF at `url`. Error pages, even when served with a success status, are not PDFs and fail.
+async fn fetch_pdf<F: FnMut(u64, Option<u64>)>(url: &str, mut on_progress: F) -> Result<Vec<u8>, RequestError> {
+    throttle::wait_for_window().await;
     info!("Requesting paper from: {}", url);
 
-    let client = REQWEST_CLIENT.get(url).send().await;
-    let res = match client {
-        Ok(response) => {
-            let body = response.bytes().await;
-            match body {
-                Ok(body) => Ok(body),
-                Err(e) => Err(RequestError::ReqwestError(e)),
+    let mut response = match REQWEST_CLIENT.get(url).send().await.and_then(|x| x.error_for_status()) {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Error: {:?}", e);
+            return Err(RequestError::ReqwestError(e));
+        }
+    };
+    let total = response.content_length();
+    let mut body = Vec::with_capacity(total.unwrap_or(0) as usize);
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                throttle::consume(chunk.len()).await;
+                body.extend_from_slice(&chunk);
+                on_progress(body.len() as u64, total);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!("Error: {:?}", e);
+                return Err(RequestError::ReqwestError(e));
             }
         }
-        Err(e) => Err(RequestError::ReqwestError(e)),
+    }
+    if !body.starts_with(b"%PDF-") {
+        error!("Error: {} is not a PDF.", url);
+        return Err(RequestError::NotFound("The response is not a PDF."));
+    }
+    Ok(body)
+}
+
+/// Like `save_paper`, reporting progress as `fetch_paper_with_progress` does. Returns the size of the saved file.
+pub async fn save_paper_with_progress<F: FnMut(u64, Option<u64>)>(
+    syllabus: &SyllabusCode,
+    paper: &Paper,
+    output_file: &PathBuf,
+    on_progress: F,
+) -> Result<u64, RequestError> {
+    let body = fetch_paper_with_progress(syllabus, paper, on_progress).await?;
+
+    // Renamed into place, so a paper deduplicated into a hardlink or symlink is replaced, not written through.
+    let partial = output_file.with_extension("download");
+    let written = match tokio::fs::write(&partial, &body).await {
+        Ok(_) => tokio::fs::rename(&partial, output_file).await,
+        Err(e) => Err(e),
     };
+    match written {
+        Ok(_) => {
+            info!("Saved paper to: {:?}", output_file);
+            Ok(body.len() as u64)
+        }
+        Err(e) => {
+            error!("Error saving paper: {:?}", e);
+            Err(RequestError::TokioError(e))
+        }
+    }
+}
 
-    if let Err(e) = res {
-        error!("Error: {:?}", e);
-        return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serves one response to one request on a local port, returning its URL.
+    async fn serve(status: &'static str, body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+        format!("http://{}/9709_s23_qp_12.pdf", address)
     }
 
-    let body = res.unwrap();
-    match std::fs::write(output_file, body.as_ref()) {
-        Ok(_) => info!("Saved paper to: {:?}", output_file),
-        Err(e) => error!("Error saving paper: {:?}", e),
+    #[tokio::test]
+    async fn error_statuses_fail() {
+        for status in ["404 Not Found", "500 Internal Server Error"] {
+            let url = serve(status, "<html>Not here</html>").await;
+            assert!(fetch_pdf(&url, |_, _| {}).await.is_err(), "{}", status);
+        }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn pages_that_are_not_pdfs_fail() {
+        let url = serve("200 OK", "<html>Not here</html>").await;
+        assert!(fetch_pdf(&url, |_, _| {}).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pdfs_are_returned() {
+        let url = serve("200 OK", "%PDF-1.7 paper").await;
+        assert_eq!(fetch_pdf(&url, |_, _| {}).await.unwrap(), b"%PDF-1.7 paper");
+    }
+}
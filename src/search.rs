@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    configuration::{PaperType, Season, SYLLABUS_CODES},
+    layout::Layout,
+    library::{scan_library, LibraryPaper, METADATA_DIR},
+    pdf::extract_pages,
+};
+
+const INDEX_FILE: &str = "index.json";
+const SNIPPET_RADIUS: usize = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedDocument {
+    pub path: PathBuf,
+    pub subject: String,
+    pub syllabus_code: String,
+    pub year: String,
+    pub season: Season,
+    pub paper_type: PaperType,
+    pub variant: String,
+    pub modified: u64,
+    pub pages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Posting {
+    pub document: usize,
+    pub page: usize,
+    pub count: u32,
+}
+
+/// Inverted index over the text of every page in the library.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    pub documents: Vec<IndexedDocument>,
+    pub terms: HashMap<String, Vec<Posting>>,
+}
+
+#[derive(Debug)]
+pub struct IndexConfiguration {
+    pub input_folder: PathBuf,
+    pub layout: Layout,
+    pub index: Option<PathBuf>,
+    pub threads: u8,
+}
+
+#[derive(Debug)]
+pub struct SearchConfiguration {
+    pub input_folder: PathBuf,
+    pub index: Option<PathBuf>,
+    pub query: String,
+    pub subjects: Option<Vec<String>>,
+    pub years: Option<Vec<String>>,
+    pub seasons: Option<Vec<Season>>,
+    pub papers: Option<Vec<PaperType>>,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit<'a> {
+    pub document: &'a IndexedDocument,
+    pub page: usize,
+    pub score: f64,
+    pub snippet: String,
+}
+
+/// Splits text into lowercase alphanumeric terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| x.chars().count() > 1)
+        .map(|x| x.to_lowercase())
+        .collect()
+}
+
+fn default_index_path(input_folder: &Path) -> PathBuf {
+    input_folder.join(METADATA_DIR).join(INDEX_FILE)
+}
+
+fn modified_time(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+impl SearchIndex {
+    pub fn load(path: &Path) -> Option<SearchIndex> {
+        let content = std::fs::read(path).ok()?;
+        match serde_json::from_slice(&content) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                error!("Failed to read search index {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn rebuild_terms(&mut self) {
+        self.terms.clear();
+        for (document, indexed) in self.documents.iter().enumerate() {
+            for (page, text) in indexed.pages.iter().enumerate() {
+                let mut counts: HashMap<String, u32> = HashMap::new();
+                for term in tokenize(text) {
+                    *counts.entry(term).or_default() += 1;
+                }
+                for (term, count) in counts {
+                    self.terms.entry(term).or_default().push(Posting {
+                        document,
+                        page,
+                        count,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Ranks pages by TF-IDF over the query terms, keeping only documents accepted by `filter`.
+    pub fn search<F>(&self, query: &str, filter: F, limit: usize) -> Vec<SearchHit<'_>>
+    where
+        F: Fn(&IndexedDocument) -> bool,
+    {
+        let total_pages = self.documents.iter().map(|x| x.pages.len()).sum::<usize>().max(1) as f64;
+        let query_terms = tokenize(query);
+        let phrase = query.to_lowercase();
+
+        let mut scores: HashMap<(usize, usize), f64> = HashMap::new();
+        for term in &query_terms {
+            let postings = match self.terms.get(term) {
+                Some(postings) => postings,
+                None => continue,
+            };
+            let idf = (total_pages / postings.len() as f64).ln() + 1.0;
+            for posting in postings {
+                if !filter(&self.documents[posting.document]) {
+                    continue;
+                }
+                *scores.entry((posting.document, posting.page)).or_default() +=
+                    (1.0 + (posting.count as f64).ln()) * idf;
+            }
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .map(|((document, page), mut score)| {
+                let text = &self.documents[document].pages[page];
+                // Pages containing the whole query as written rank above scattered matches.
+                if query_terms.len() > 1 && text.to_lowercase().contains(&phrase) {
+                    score *= 2.0;
+                }
+                SearchHit {
+                    document: &self.documents[document],
+                    page,
+                    score,
+                    snippet: snippet(text, &query_terms),
+                }
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        hits
+    }
+}
+
+fn snippet(text: &str, terms: &[String]) -> String {
+    let lower = text.to_lowercase();
+    // Lowercasing can change byte offsets for some scripts, fall back to the start of the page.
+    let position = terms
+        .iter()
+        .filter_map(|x| lower.find(x.as_str()))
+        .min()
+        .filter(|_| lower.len() == text.len())
+        .unwrap_or(0);
+    let mut start = position.saturating_sub(SNIPPET_RADIUS);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (position + SNIPPET_RADIUS).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    text[start..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn index_document(entry: LibraryPaper) -> Option<IndexedDocument> {
+    let pages = match extract_pages(&entry.path) {
+        Ok(pages) => pages,
+        Err(e) => {
+            error!("Skipping {:?}: {}", entry.path, e);
+            return None;
+        }
+    };
+    info!("Indexed {:?} ({} pages)", entry.path, pages.len());
+    Some(IndexedDocument {
+        modified: modified_time(&entry.path),
+        path: entry.path,
+        subject: entry.syllabus_code.name,
+        syllabus_code: entry.syllabus_code.syllabus_code,
+        year: entry.paper.year,
+        season: entry.paper.season,
+        paper_type: entry.paper.paper_type,
+        variant: entry.paper.variant,
+        pages,
+    })
+}
+
+pub fn handle_index(config: IndexConfiguration) {
+    let index_path = config
+        .index
+        .unwrap_or(default_index_path(&config.input_folder));
+    let entries = scan_library(&config.input_folder, &config.layout);
+    if entries.is_empty() {
+        error!("No papers found in {:?}", config.input_folder);
+        std::process::exit(1);
+    }
+
+    // Reuse documents that haven't changed since the last run.
+    let mut previous = SearchIndex::load(&index_path)
+        .map(|x| {
+            x.documents
+                .into_iter()
+                .map(|doc| (doc.path.clone(), doc))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+    let mut documents = vec![];
+    let mut pending = vec![];
+    for entry in entries {
+        match previous.remove(&entry.path) {
+            Some(doc) if doc.modified == modified_time(&entry.path) => documents.push(doc),
+            _ => pending.push(entry),
+        }
+    }
+    info!(
+        "Indexing {} papers, {} unchanged.",
+        pending.len(),
+        documents.len()
+    );
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.threads as usize)
+        .enable_all()
+        .build();
+    let rt = match rt {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("Failed to create tokio runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let indexed = rt.block_on(async {
+        futures::stream::iter(pending)
+            .map(|entry| async move {
+                tokio::task::spawn_blocking(move || index_document(entry))
+                    .await
+                    .ok()
+                    .flatten()
+            })
+            .buffer_unordered(config.threads as usize)
+            .collect::<Vec<_>>()
+            .await
+    });
+    documents.extend(indexed.into_iter().flatten());
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut index = SearchIndex {
+        documents,
+        terms: HashMap::new(),
+    };
+    index.rebuild_terms();
+
+    if let Some(parent) = index_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            error!("Failed to create index folder: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let written = File::create(&index_path)
+        .and_then(|mut file| file.write_all(&serde_json::to_vec(&index).unwrap()));
+    match written {
+        Ok(_) => info!(
+            "Indexed {} papers ({} terms) into {:?}",
+            index.documents.len(),
+            index.terms.len(),
+            index_path
+        ),
+        Err(e) => {
+            error!("Failed to write search index: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn handle_search(config: SearchConfiguration) {
+    let index_path = config
+        .index
+        .unwrap_or(default_index_path(&config.input_folder));
+    let index = match SearchIndex::load(&index_path) {
+        Some(index) => index,
+        None => {
+            error!(
+                "No search index found at {:?}, run the index command first.",
+                index_path
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // Every syllabus a --subject refers to, by name or code prefix.
+    let syllabus_codes = config.subjects.as_ref().map(|subjects| {
+        let mut codes: Vec<String> = vec![];
+        for subject in subjects {
+            let matching = SYLLABUS_CODES.iter().filter(|x| x.matches(subject)).collect::<Vec<_>>();
+            if matching.is_empty() {
+                error!("Invalid subject code: {}", subject);
+                std::process::exit(1);
+            }
+            codes.extend(matching.into_iter().map(|x| x.syllabus_code.clone()));
+        }
+        codes
+    });
+    let filter = |doc: &IndexedDocument| {
+        syllabus_codes.as_ref().is_none_or(|x| x.contains(&doc.syllabus_code))
+            && config.years.as_ref().is_none_or(|x| x.contains(&doc.year))
+            && config.seasons.as_ref().is_none_or(|x| x.contains(&doc.season))
+            && config.papers.as_ref().is_none_or(|x| x.contains(&doc.paper_type))
+    };
+
+    let hits = index.search(&config.query, filter, config.limit);
+    if hits.is_empty() {
+        info!("No results for \"{}\".", config.query);
+        return;
+    }
+    for (rank, hit) in hits.iter().enumerate() {
+        println!(
+            "{:>3}. {} ({}) {} {} {} {} - page {} [{:.2}]",
+            rank + 1,
+            hit.document.subject,
+            hit.document.syllabus_code,
+            hit.document.season.session_name(),
+            hit.document.year,
+            hit.document.paper_type,
+            hit.document.variant,
+            hit.page + 1,
+            hit.score
+        );
+        println!("     {}", hit.document.path.display());
+        println!("     ...{}...", hit.snippet);
+    }
+}
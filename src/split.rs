@@ -0,0 +1,166 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    configuration::{PaperType, Season},
+    layout::Layout,
+    library::{scan_library_by_type, LibraryPaper},
+    pdf::{extract_placed_words, load, save_pages, PlacedPage},
+};
+
+/// Question numbers are only trusted when they sit in this fraction of the page width from the left edge.
+const MARGIN_FRACTION: f64 = 0.15;
+
+#[derive(Debug)]
+pub struct SplitConfiguration {
+    pub input_folder: PathBuf,
+    pub layout: Layout,
+    pub output_folder: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuestionFile {
+    pub question: u32,
+    pub file: String,
+    pub pages: Vec<u32>,
+}
+
+/// Sidecar written next to the split questions of a paper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitMetadata {
+    pub syllabus_code: String,
+    pub subject: String,
+    pub session: String,
+    pub year: String,
+    pub season: Season,
+    pub component: String,
+    pub variant: String,
+    pub source: String,
+    pub questions: Vec<QuestionFile>,
+}
+
+/// Finds the page each question starts on from the question numbers printed in the left margin.
+/// Numbers must appear in sequence, so stray digits in the margin are ignored.
+pub fn find_question_starts(pages: &[PlacedPage]) -> Vec<(u32, u32)> {
+    let mut starts = vec![];
+    let mut expected = 1;
+    for page in pages {
+        let mut markers = page
+            .words
+            .iter()
+            .filter(|x| x.x - page.left < page.width * MARGIN_FRACTION)
+            .filter_map(|x| x.text.trim_end_matches('.').parse::<u32>().ok().map(|n| (n, x.y)))
+            .collect::<Vec<_>>();
+        // Top of the page first, PDF y grows upwards.
+        markers.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (number, _) in markers {
+            if number == expected {
+                starts.push((number, page.number));
+                expected += 1;
+            }
+        }
+    }
+    starts
+}
+
+/// Turns question start pages into page ranges. A question shares its last page with
+/// the next one when both start on the same page. Trailing blank pages are dropped.
+pub fn question_pages(starts: &[(u32, u32)], pages: &[PlacedPage]) -> Vec<(u32, Vec<u32>)> {
+    let last_page = pages
+        .iter()
+        .rev()
+        .find(|x| {
+            let text = x.words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" ");
+            !text.contains("BLANK PAGE") && !text.contains("Permission to reproduce")
+        })
+        .map(|x| x.number)
+        .unwrap_or(0);
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, (question, start))| {
+            let end = match starts.get(i + 1) {
+                Some((_, next)) if next > start => next - 1,
+                Some((_, next)) => *next,
+                None => last_page.max(*start),
+            };
+            (*question, (*start..=end).collect())
+        })
+        .collect()
+}
+
+fn split_paper(entry: &LibraryPaper, config: &SplitConfiguration) -> Result<usize, String> {
+    let pages = extract_placed_words(&entry.path).map_err(|e| e.to_string())?;
+    let starts = find_question_starts(&pages);
+    if starts.is_empty() {
+        return Err("no question markers found".to_string());
+    }
+    let document = load(&entry.path).map_err(|e| e.to_string())?;
+
+    let folder = config
+        .output_folder
+        .join(format!(
+            "{} ({})",
+            entry.syllabus_code.name, entry.syllabus_code.syllabus_code
+        ))
+        .join(&entry.paper.year);
+    std::fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+
+    let stem = entry
+        .paper
+        .get_ref_filename(&entry.syllabus_code)
+        .trim_end_matches(".pdf")
+        .to_string();
+    let mut questions = vec![];
+    for (question, pages) in question_pages(&starts, &pages) {
+        let file = format!("{}_q{:02}.pdf", stem, question);
+        save_pages(&document, &pages, &folder.join(&file)).map_err(|e| e.to_string())?;
+        questions.push(QuestionFile {
+            question,
+            file,
+            pages,
+        });
+    }
+
+    let metadata = SplitMetadata {
+        syllabus_code: entry.syllabus_code.syllabus_code.clone(),
+        subject: entry.syllabus_code.name.clone(),
+        session: format!("{} {}", entry.paper.season.session_name(), entry.paper.year),
+        year: entry.paper.year.clone(),
+        season: entry.paper.season.clone(),
+        component: entry.paper.component().to_string(),
+        variant: entry.paper.variant_number().to_string(),
+        source: entry.paper.get_ref_filename(&entry.syllabus_code),
+        questions,
+    };
+    File::create(folder.join(format!("{}.json", stem)))
+        .and_then(|mut file| file.write_all(&serde_json::to_vec_pretty(&metadata).unwrap()))
+        .map_err(|e| e.to_string())?;
+    Ok(metadata.questions.len())
+}
+
+pub fn handle_split(config: SplitConfiguration) {
+    let entries = scan_library_by_type(&config.input_folder, &config.layout, PaperType::QP);
+    if entries.is_empty() {
+        error!("No question papers found in {:?}", config.input_folder);
+        std::process::exit(1);
+    }
+    info!("Splitting {} question papers.", entries.len());
+
+    let mut total = 0;
+    for entry in &entries {
+        match split_paper(entry, &config) {
+            Ok(count) => {
+                info!("Split {:?} into {} questions.", entry.path, count);
+                total += count;
+            }
+            Err(e) => error!("Failed to split {:?}: {}", entry.path, e),
+        }
+    }
+    info!(
+        "Wrote {} questions to {:?}",
+        total, config.output_folder
+    );
+}
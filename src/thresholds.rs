@@ -0,0 +1,252 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    configuration::{PaperType, Season},
+    layout::Layout,
+    library::{scan_library_by_type, LibraryPaper},
+    pdf::extract_text,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ThresholdKind {
+    Component,
+    Option,
+}
+
+/// One row of a grade threshold table.
+#[derive(Debug, Clone, Serialize)]
+pub struct GradeThreshold {
+    pub syllabus_code: String,
+    pub session: String,
+    pub year: String,
+    pub season: Season,
+    pub kind: ThresholdKind,
+    pub name: String,
+    pub max_mark: u32,
+    pub a_star: Option<u32>,
+    pub a: Option<u32>,
+    pub b: Option<u32>,
+    pub c: Option<u32>,
+    pub d: Option<u32>,
+    pub e: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct ThresholdConfiguration {
+    pub input_folder: PathBuf,
+    pub layout: Layout,
+    pub output: PathBuf,
+    pub format: ExportFormat,
+}
+
+/// Parses the text of a grade threshold PDF into its component and option rows.
+pub fn parse_thresholds(text: &str, entry: &LibraryPaper) -> Vec<GradeThreshold> {
+    let option_matcher = Regex::new(r"^[A-Z][A-Z0-9]{1,2}$").unwrap();
+    let mut has_a_star: Option<bool> = None;
+    let mut thresholds = vec![];
+
+    for line in text.lines() {
+        let line = line.replace(['–', '—'], "-");
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        // Header rows tell us whether the following table has an A* column.
+        if tokens.windows(5).any(|x| x == ["A", "B", "C", "D", "E"]) {
+            has_a_star = Some(tokens.contains(&"A*"));
+            continue;
+        }
+
+        let (kind, name, rest) = if tokens.len() > 2
+            && (tokens[0].eq_ignore_ascii_case("component") || tokens[0].eq_ignore_ascii_case("paper"))
+            && tokens[1].chars().all(|c| c.is_ascii_digit())
+        {
+            (ThresholdKind::Component, tokens[1], &tokens[2..])
+        } else if tokens.len() > 1 && option_matcher.is_match(tokens[0]) {
+            (ThresholdKind::Option, tokens[0], &tokens[1..])
+        } else {
+            continue;
+        };
+
+        // Marks are the trailing run of numbers or "-" (grade not available).
+        let marks: Vec<Option<u32>> = rest
+            .iter()
+            .rev()
+            .map_while(|x| match *x {
+                "-" => Some(None),
+                x => x.parse::<u32>().ok().map(Some),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+
+        let grade_count = match (kind, has_a_star) {
+            (ThresholdKind::Component, _) if marks.len() == 6 || marks.len() == 7 => marks.len() - 1,
+            (_, Some(true)) => 6,
+            (_, Some(false)) => 5,
+            (_, None) if marks.len() >= 7 => 6,
+            (_, None) => 5,
+        };
+        if marks.len() < grade_count + 1 {
+            continue;
+        }
+        let marks = &marks[marks.len() - grade_count - 1..];
+        let max_mark = match marks[0] {
+            Some(max_mark) => max_mark,
+            None => continue,
+        };
+        let mut grades = marks[1..].to_vec();
+        if grade_count == 5 {
+            grades.insert(0, None);
+        }
+
+        thresholds.push(GradeThreshold {
+            syllabus_code: entry.syllabus_code.syllabus_code.clone(),
+            session: format!("{} {}", entry.paper.season.session_name(), entry.paper.year),
+            year: entry.paper.year.clone(),
+            season: entry.paper.season.clone(),
+            kind,
+            name: name.to_string(),
+            max_mark,
+            a_star: grades[0],
+            a: grades[1],
+            b: grades[2],
+            c: grades[3],
+            d: grades[4],
+            e: grades[5],
+        });
+    }
+    thresholds
+}
+
+pub fn handle_thresholds(config: ThresholdConfiguration) {
+    let entries = scan_library_by_type(&config.input_folder, &config.layout, PaperType::GT);
+    if entries.is_empty() {
+        error!("No grade threshold papers found in {:?}", config.input_folder);
+        std::process::exit(1);
+    }
+    info!("Parsing {} grade threshold papers.", entries.len());
+
+    let mut thresholds = vec![];
+    for entry in &entries {
+        let text = match extract_text(&entry.path) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Skipping {:?}: {}", entry.path, e);
+                continue;
+            }
+        };
+        let parsed = parse_thresholds(&text, entry);
+        if parsed.is_empty() {
+            warn!("No threshold rows found in {:?}", entry.path);
+        }
+        debug!("Parsed {} rows from {:?}", parsed.len(), entry.path);
+        thresholds.extend(parsed);
+    }
+
+    let output = match config.format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for threshold in &thresholds {
+                if let Err(e) = writer.serialize(threshold) {
+                    error!("Failed to serialise threshold: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            match writer.into_inner() {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Failed to serialise thresholds: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ExportFormat::Json => serde_json::to_vec_pretty(&thresholds).unwrap(),
+    };
+
+    let written = File::create(&config.output).and_then(|mut file| file.write_all(&output));
+    match written {
+        Ok(_) => info!(
+            "Wrote {} threshold rows to {:?}",
+            thresholds.len(),
+            config.output
+        ),
+        Err(e) => {
+            error!("Failed to write thresholds: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{Paper, SyllabusCode};
+
+    /// Text extracted from the June 2023 grade thresholds of 9709.
+    const FIXTURE: &str = include_str!("../tests/fixtures/9709_s23_gt.txt");
+
+    fn entry() -> LibraryPaper {
+        LibraryPaper {
+            path: PathBuf::from("9709_s23_gt.pdf"),
+            syllabus_code: SyllabusCode::find("9709").unwrap(),
+            paper: Paper::new("2023", Season::Summer, PaperType::GT, ""),
+        }
+    }
+
+    fn row(threshold: &GradeThreshold) -> (ThresholdKind, &str, u32, [Option<u32>; 6]) {
+        (
+            threshold.kind,
+            threshold.name.as_str(),
+            threshold.max_mark,
+            [threshold.a_star, threshold.a, threshold.b, threshold.c, threshold.d, threshold.e],
+        )
+    }
+
+    #[test]
+    fn parses_components_and_options() {
+        let thresholds = parse_thresholds(FIXTURE, &entry());
+        let rows = thresholds.iter().map(row).collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (ThresholdKind::Component, "11", 75, [None, Some(56), Some(47), Some(38), Some(29), Some(20)]),
+                (ThresholdKind::Component, "12", 75, [None, Some(51), Some(42), Some(34), Some(26), Some(18)]),
+                (ThresholdKind::Component, "13", 75, [None, Some(57), Some(48), Some(39), Some(30), Some(21)]),
+                (ThresholdKind::Component, "31", 75, [None, Some(54), Some(45), Some(36), Some(27), Some(19)]),
+                // A dash is a grade that isn't available.
+                (ThresholdKind::Component, "42", 50, [None, None, Some(30), Some(24), Some(18), Some(12)]),
+                (ThresholdKind::Component, "51", 50, [None, Some(35), Some(29), Some(23), Some(17), Some(11)]),
+                (ThresholdKind::Option, "AX", 250, [Some(219), Some(180), Some(146), Some(112), Some(79), Some(46)]),
+                (ThresholdKind::Option, "FS", 250, [Some(204), Some(170), Some(138), Some(106), Some(74), Some(42)]),
+                (ThresholdKind::Option, "AS", 125, [None, Some(92), Some(77), Some(62), Some(47), Some(33)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_name_the_paper_they_came_from() {
+        let thresholds = parse_thresholds(FIXTURE, &entry());
+        assert!(thresholds.iter().all(|x| x.syllabus_code == "9709"
+            && x.session == "May/June 2023"
+            && x.year == "2023"
+            && x.season == Season::Summer));
+    }
+
+    #[test]
+    fn text_without_tables_has_no_rows() {
+        assert!(parse_thresholds("Grade thresholds - June 2023\nNo table here.", &entry()).is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Timelike;
+
+const DAY: u32 = 24 * 60;
+
+/// A download speed, written like "500K", "2MB", "1.5MiB/s" or a plain number of bytes per second.
+/// A lowercase "b" counts bits, so "8Mb" is 1MB.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rate {
+    text: String,
+    bytes_per_second: u64,
+}
+
+impl Rate {
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid bandwidth \"{}\", expected e.g. \"500K\", \"2MB\" or \"1MiB/s\"", text);
+        let value = text.trim();
+        let value = value.strip_suffix("/s").unwrap_or(value);
+        let (value, bits) = match value.strip_suffix('b') {
+            Some(value) => (value, true),
+            None => (value.strip_suffix('B').unwrap_or(value), false),
+        };
+        let split = value.find(|x: char| !x.is_ascii_digit() && x != '.').unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let multiplier = match unit.trim() {
+            "" => 1.0,
+            "k" | "K" => 1e3,
+            "M" => 1e6,
+            "G" => 1e9,
+            "Ki" => 1024.0,
+            "Mi" => 1024.0 * 1024.0,
+            "Gi" => 1024.0 * 1024.0 * 1024.0,
+            _ => return Err(invalid()),
+        };
+        let bytes_per_second = match bits {
+            true => (number * multiplier / 8.0) as u64,
+            false => (number * multiplier) as u64,
+        };
+        if bytes_per_second == 0 {
+            return Err(format!("Bandwidth \"{}\" must be at least one byte per second", text));
+        }
+        Ok(Rate {
+            text: text.to_string(),
+            bytes_per_second,
+        })
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Rate::from_str(&value)
+    }
+}
+
+impl From<Rate> for String {
+    fn from(value: Rate) -> Self {
+        value.text
+    }
+}
+
+impl std::fmt::Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Daily local time range downloads may run in, e.g. "18:00-07:00". Ranges may cross midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Window {
+    /// Minutes after midnight.
+    start: u32,
+    end: u32,
+}
+
+fn parse_time(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    match hours * 60 + minutes {
+        x if minutes < 60 && x <= DAY => Some(x),
+        _ => None,
+    }
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        match self.start < self.end {
+            true => self.start <= minute && minute < self.end,
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid download window \"{}\", expected e.g. \"18:00-07:00\"", text);
+        let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (parse_time(start).ok_or_else(invalid)?, parse_time(end).ok_or_else(invalid)?);
+        // 24:00 is midnight again, only as the end does it mean the end of the day.
+        if start == end || start % DAY == end {
+            return Err(format!("Download window \"{}\" is empty", text));
+        }
+        Ok(Window {
+            start: start % DAY,
+            end,
+        })
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Window::from_str(&value)
+    }
+}
+
+impl From<Window> for String {
+    fn from(value: Window) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// When and how fast papers may be downloaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    /// Shared by every transfer, unlimited when unset.
+    pub bandwidth: Option<Rate>,
+    /// Any time when empty.
+    pub windows: Vec<Window>,
+}
+
+impl Schedule {
+    /// Settings given here win, the others come from `fallback`.
+    pub fn or(self, fallback: Schedule) -> Schedule {
+        Schedule {
+            bandwidth: self.bandwidth.or(fallback.bandwidth),
+            windows: match self.windows.is_empty() {
+                true => fallback.windows,
+                false => self.windows,
+            },
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.bandwidth.is_none() && self.windows.is_empty()
+    }
+}
+
+struct Throttle {
+    schedule: Schedule,
+    /// When the bandwidth allows the next byte through.
+    next: Mutex<Instant>,
+    paused: AtomicBool,
+}
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// Applies `schedule` to every paper downloaded from now on. Only the first call counts.
+pub fn set_schedule(schedule: Schedule) {
+    if schedule.is_unlimited() {
+        return;
+    }
+    if let Some(bandwidth) = &schedule.bandwidth {
+        info!(
+            "Limiting downloads to {}/s in total.",
+            indicatif::HumanBytes(bandwidth.bytes_per_second())
+        );
+    }
+    if !schedule.windows.is_empty() {
+        let windows = schedule.windows.iter().map(Window::to_string).collect::<Vec<_>>();
+        info!("Downloading only between {}.", windows.join(", "));
+    }
+    let throttle = Throttle {
+        schedule,
+        next: Mutex::new(Instant::now()),
+        paused: AtomicBool::new(false),
+    };
+    if THROTTLE.set(throttle).is_err() {
+        warn!("The download schedule was set already, ignoring the new one.");
+    }
+}
+
+/// Waits until a download window is open. Called before every chunk, so running transfers pause too.
+pub async fn wait_for_window() {
+    let throttle = match THROTTLE.get() {
+        Some(throttle) if !throttle.schedule.windows.is_empty() => throttle,
+        _ => return,
+    };
+    loop {
+        let now = chrono::Local::now();
+        let second = now.num_seconds_from_midnight();
+        let minute = second / 60;
+        if throttle.schedule.windows.iter().any(|x| x.contains(minute)) {
+            if throttle.paused.swap(false, Ordering::SeqCst) {
+                warn!("Download window open, resuming.");
+            }
+            return;
+        }
+        let until = |x: &Window| (x.start * 60 + DAY * 60 - second) % (DAY * 60);
+        let opens = throttle.schedule.windows.iter().min_by_key(|x| until(x)).unwrap();
+        let wait = until(opens);
+        if !throttle.paused.swap(true, Ordering::SeqCst) {
+            warn!(
+                "Outside the download windows, pausing until {:02}:{:02}.",
+                opens.start / 60,
+                opens.start % 60
+            );
+        }
+        // Checked again on waking, the clock may have moved.
+        tokio::time::sleep(Duration::from_secs(wait.max(1) as u64)).await;
+    }
+}
+
+/// Holds a transfer back until a download window is open and `bytes` more fit into the bandwidth shared by
+/// all transfers.
+pub async fn consume(bytes: usize) {
+    wait_for_window().await;
+    let (throttle, rate) = match THROTTLE.get() {
+        Some(throttle) => match &throttle.schedule.bandwidth {
+            Some(rate) => (throttle, rate.bytes_per_second()),
+            None => return,
+        },
+        None => return,
+    };
+    let wait = {
+        let mut next = throttle.next.lock().unwrap();
+        let now = Instant::now();
+        // Idle time builds up at most a second of credit.
+        let start = (*next).max(now.checked_sub(Duration::from_secs(1)).unwrap_or(now));
+        *next = start + Duration::from_secs_f64(bytes as f64 / rate as f64);
+        next.saturating_duration_since(now)
+    };
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates() {
+        for (text, bytes_per_second) in [
+            ("1000", 1000),
+            ("500K", 500_000),
+            ("500k", 500_000),
+            ("500KB", 500_000),
+            ("500Kb", 62_500),
+            ("2MB/s", 2_000_000),
+            ("8Mb/s", 1_000_000),
+            ("1.5MiB/s", 1_572_864),
+            ("1KiB", 1024),
+            ("1G", 1_000_000_000),
+            ("16b", 2),
+            ("1Kib", 128),
+        ] {
+            assert_eq!(Rate::from_str(text).map(|x| x.bytes_per_second()), Ok(bytes_per_second), "{}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_rates() {
+        for text in ["", "K", "fast", "5 TB", "0", "0.1", "4b", "1Tb"] {
+            assert!(Rate::from_str(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn rates_keep_their_text() {
+        let rate = Rate::from_str("1.5MiB/s").unwrap();
+        assert_eq!(rate.to_string(), "1.5MiB/s");
+    }
+
+    #[test]
+    fn windows() {
+        let minute = |hours: u32, minutes: u32| hours * 60 + minutes;
+        for (text, inside, outside) in [
+            ("09:00-17:00", vec![minute(9, 0), minute(16, 59)], vec![minute(8, 59), minute(17, 0)]),
+            // Crossing midnight.
+            ("18:00-07:00", vec![minute(18, 0), minute(23, 59), 0, minute(6, 59)], vec![minute(7, 0), minute(17, 59)]),
+            ("22:00-00:00", vec![minute(22, 0), minute(23, 59)], vec![0, minute(21, 59)]),
+            ("00:00-24:00", vec![0, minute(12, 0), minute(23, 59)], vec![]),
+            ("22:00-24:00", vec![minute(22, 0), minute(23, 59)], vec![0, minute(21, 59)]),
+            ("24:00-06:00", vec![0, minute(5, 59)], vec![minute(6, 0), minute(23, 59)]),
+        ] {
+            let window = Window::from_str(text).unwrap();
+            for minute in inside {
+                assert!(window.contains(minute), "{} should contain minute {}", text, minute);
+            }
+            for minute in outside {
+                assert!(!window.contains(minute), "{} should not contain minute {}", text, minute);
+            }
+        }
+    }
+
+    #[test]
+    fn windows_print_as_written() {
+        for text in ["09:00-17:00", "18:00-07:00", "00:00-24:00", "22:00-00:00"] {
+            assert_eq!(Window::from_str(text).unwrap().to_string(), text);
+        }
+        assert_eq!(Window::from_str("9:30-10:00").unwrap().to_string(), "09:30-10:00");
+        assert_eq!(Window::from_str("24:00-06:00").unwrap().to_string(), "00:00-06:00");
+    }
+
+    #[test]
+    fn invalid_windows() {
+        for text in [
+            "",
+            "18:00",
+            "18-07",
+            "25:00-07:00",
+            "18:60-07:00",
+            "24:01-07:00",
+            "07:00-07:00",
+            "24:00-00:00",
+            "00:00-00:00",
+            "24:00-24:00",
+        ] {
+            assert!(Window::from_str(text).is_err(), "{} was accepted", text);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use futures::{stream, StreamExt};
+
+use crate::{
+    config_gen::runtime,
+    configuration::{Paper, SyllabusCode, SYLLABUS_CODES},
+    library::collect_files,
+    manifest::{fingerprint, library_path, relative_path, Manifest, ManifestEntry},
+    scraper::save_paper,
+    throttle::{set_schedule, Schedule},
+};
+
+#[derive(Debug)]
+pub struct VerifyConfiguration {
+    pub input_folder: PathBuf,
+    /// Download the missing and damaged papers again.
+    pub repair: bool,
+    pub threads: u8,
+    /// Bandwidth and time windows of the repair downloads.
+    pub schedule: Schedule,
+}
+
+/// What is wrong with a file of the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// Recorded but gone.
+    Missing,
+    /// Smaller than when it was downloaded.
+    Truncated,
+    /// Same size or larger, but different content.
+    Corrupted,
+    /// On disk but not downloaded by the scraper.
+    Unexpected,
+}
+
+impl Problem {
+    /// Whether downloading the paper again fixes it.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Problem::Unexpected)
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Problem::Missing => "missing",
+            Problem::Truncated => "truncated",
+            Problem::Corrupted => "corrupted",
+            Problem::Unexpected => "unexpected",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// Relative to the library root.
+    pub path: String,
+    pub problem: Problem,
+    /// The manifest entry of the file, `None` for unexpected files.
+    pub entry: Option<ManifestEntry>,
+}
+
+/// The result of checking a library against its manifest.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    pub checked: usize,
+    /// Files recorded before checksums were kept, only their presence is checked.
+    pub unverified: usize,
+}
+
+/// Compares every file of the library with the size and checksum recorded when it was downloaded.
+pub fn check_library(root: &std::path::Path, manifest: &Manifest) -> Report {
+    let mut report = Report::default();
+    for entry in &manifest.files {
+        let path = library_path(root, &entry.path);
+        let problem = match std::fs::metadata(&path) {
+            Ok(x) if x.is_file() => {
+                if entry.sha256.is_empty() {
+                    report.unverified += 1;
+                    None
+                } else if x.len() < entry.size {
+                    Some(Problem::Truncated)
+                } else {
+                    match fingerprint(&path) {
+                        Ok((size, sha256)) if size == entry.size && sha256 == entry.sha256 => None,
+                        Ok(_) => Some(Problem::Corrupted),
+                        Err(e) => {
+                            error!("Failed to read {:?}: {}", path, e);
+                            Some(Problem::Corrupted)
+                        }
+                    }
+                }
+            }
+            _ => Some(Problem::Missing),
+        };
+        report.checked += 1;
+        if let Some(problem) = problem {
+            report.findings.push(Finding {
+                path: entry.path.clone(),
+                problem,
+                entry: Some(entry.clone()),
+            });
+        }
+    }
+    for path in collect_files(root) {
+        match relative_path(root, &path) {
+            Some(relative) if manifest.get(&relative).is_none() => report.findings.push(Finding {
+                path: relative,
+                problem: Problem::Unexpected,
+                entry: None,
+            }),
+            _ => {}
+        }
+    }
+    report
+}
+
+/// Downloads the papers of `findings` again, updating their manifest entries. Returns the number repaired.
+/// The syllabus a manifest entry was downloaded from. Neither the code nor the path tells apart syllabi sharing a
+/// code, e.g. both Islamic Studies 9013 listings, so those need the recorded access slug.
+fn recorded_syllabus(entry: &ManifestEntry) -> Result<&'static SyllabusCode, String> {
+    let syllabus_code = match entry.access_slug.is_empty() {
+        false => SYLLABUS_CODES.iter().find(|x| x.access_slug == entry.access_slug),
+        true => SYLLABUS_CODES.iter().find(|x| x.syllabus_code == entry.syllabus_code),
+    };
+    match syllabus_code {
+        Some(x) if entry.access_slug.is_empty() && x.is_ambiguous() => Err(format!(
+            "{} is shared by several syllabi and the manifest doesn't record which",
+            entry.syllabus_code
+        )),
+        Some(x) => Ok(x),
+        None => Err(format!("{} isn't a known syllabus", entry.syllabus_code)),
+    }
+}
+
+fn repair(config: &VerifyConfiguration, manifest: &mut Manifest, findings: &[&Finding]) -> usize {
+    let mut jobs = vec![];
+    for finding in findings {
+        let entry = match &finding.entry {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let syllabus_code = match recorded_syllabus(entry) {
+            Ok(syllabus_code) => syllabus_code,
+            Err(e) => {
+                error!("Can't repair {}, {}.", entry.path, e);
+                continue;
+            }
+        };
+        match Paper::from_str(&entry.file) {
+            Ok(paper) => jobs.push((syllabus_code.clone(), paper, library_path(&config.input_folder, &entry.path))),
+            Err(_) => error!("Can't repair {}, {} isn't a known paper.", entry.path, entry.file),
+        }
+    }
+    for (_, _, path) in &jobs {
+        if let Some(parent) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                error!("Failed to create folder {:?}: {}", parent, e);
+            }
+        }
+    }
+
+    set_schedule(config.schedule.clone());
+    let rt = runtime(config.threads);
+    let saved = rt.block_on(
+        stream::iter(jobs)
+            .map(|(syllabus_code, paper, path)| async move {
+                let saved = save_paper(&syllabus_code, &paper, &path).await;
+                (path, saved)
+            })
+            .buffer_unordered(config.threads.max(1) as usize)
+            .collect::<Vec<_>>(),
+    );
+
+    let mut repaired = 0;
+    for (path, saved) in saved {
+        if saved.is_err() {
+            continue;
+        }
+        match manifest.refresh(&config.input_folder, &path) {
+            Ok(_) => repaired += 1,
+            Err(e) => error!("Failed to read back {:?}: {}", path, e),
+        }
+    }
+    if let Err(e) = manifest.save(&config.input_folder) {
+        error!("Failed to save the library manifest: {}", e);
+    }
+    repaired
+}
+
+pub fn handle_verify(config: VerifyConfiguration) {
+    let mut manifest = match Manifest::load(&config.input_folder) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!("Failed to read the library manifest: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if manifest.files.is_empty() {
+        error!(
+            "{:?} has no record of downloaded papers, only papers downloaded by this version can be verified.",
+            config.input_folder
+        );
+        std::process::exit(1);
+    }
+
+    let report = check_library(&config.input_folder, &manifest);
+    for finding in &report.findings {
+        println!("{:<10} {}", finding.problem, finding.path);
+    }
+    let count = |problem: Problem| report.findings.iter().filter(|x| x.problem == problem).count();
+    info!(
+        "Checked {} papers: {} corrupted, {} truncated, {} missing, {} unexpected files.",
+        report.checked,
+        count(Problem::Corrupted),
+        count(Problem::Truncated),
+        count(Problem::Missing),
+        count(Problem::Unexpected)
+    );
+    if report.unverified > 0 {
+        warn!(
+            "{} papers were downloaded before checksums were recorded, only their presence was checked.",
+            report.unverified
+        );
+    }
+
+    let broken = report
+        .findings
+        .iter()
+        .filter(|x| x.problem.is_repairable())
+        .collect::<Vec<_>>();
+    if broken.is_empty() {
+        return;
+    }
+    if !config.repair {
+        error!("{} papers are damaged or missing, run again with --repair to download them again.", broken.len());
+        std::process::exit(1);
+    }
+    let repaired = repair(&config, &mut manifest, &broken);
+    info!("Repaired {} of {} papers.", repaired, broken.len());
+    if repaired < broken.len() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(syllabus_code: &str, access_slug: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: "9013/s23/9013_s23_qp_12.pdf".to_string(),
+            syllabus_code: syllabus_code.to_string(),
+            access_slug: access_slug.to_string(),
+            file: "9013_s23_qp_12.pdf".to_string(),
+            size: 0,
+            sha256: String::new(),
+        }
+    }
+
+    #[test]
+    fn repair_resolves_shared_codes_by_access_slug() {
+        let resolved = recorded_syllabus(&entry("9013", "islamic-studies-(9013-&-8053)")).unwrap();
+        assert_eq!(resolved.access_slug, "islamic-studies-(9013-&-8053)");
+        assert!(recorded_syllabus(&entry("9013", "")).is_err());
+        assert_eq!(recorded_syllabus(&entry("9709", "")).unwrap().syllabus_code, "9709");
+    }
+}